@@ -0,0 +1,174 @@
+use crate::event::WindowEvent;
+use crate::filter::WindowFilter;
+use std::ops::{BitOr, BitOrAssign};
+use std::time::Duration;
+
+/// Decides when a change of the active window is reported as a focus change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupStrategy {
+    /// Report only if the title differs from the last reported title.
+    #[default]
+    Title,
+    /// Report only if a different window gained focus.
+    Window,
+    /// Report if either the window or its title differs.
+    WindowAndTitle,
+    /// Report every `_NET_ACTIVE_WINDOW` update, even if nothing changed.
+    None,
+}
+
+/// What a hook does when one of its handlers panics.
+///
+/// Panics are only caught when the crate is built with `panic = "unwind"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Log the panic and keep calling the handler.
+    #[default]
+    LogAndContinue,
+    /// Log the panic and remove the handler from the hook.
+    DisableHandler,
+    /// Stop the hook; `run` returns [`WinshiftError::HandlerPanicked`](crate::WinshiftError::HandlerPanicked).
+    StopHook,
+}
+
+/// What the dispatch queue does with a new event when it is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the handlers to catch up, stalling X event processing.
+    #[default]
+    Block,
+    /// Drop the oldest queued event.
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Drop every queued event and keep only the new one.
+    CoalesceLatest,
+}
+
+/// How a hook reconnects after losing its display connection.
+///
+/// The delay between attempts starts at `initial_delay` and doubles up to `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many failed attempts; `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+/// A set of event kinds a hook reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventKinds(u32);
+
+impl EventKinds {
+    pub const FOCUS: Self = Self(1);
+    pub const TITLE: Self = Self(1 << 1);
+    pub const CREATED: Self = Self(1 << 2);
+    pub const DESTROYED: Self = Self(1 << 3);
+    pub const ERROR: Self = Self(1 << 4);
+    pub const CONNECTION: Self = Self(1 << 5);
+    pub const IDLE: Self = Self(1 << 6);
+    pub const DESKTOP: Self = Self(1 << 7);
+    pub const STATE: Self = Self(1 << 8);
+
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+
+    /// Returns `true` if every kind in `other` is also in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub(crate) fn matches(self, event: &WindowEvent) -> bool {
+        let kind = match event {
+            WindowEvent::FocusChanged(_) => Self::FOCUS,
+            WindowEvent::TitleChanged(_) => Self::TITLE,
+            WindowEvent::WindowCreated(_) => Self::CREATED,
+            WindowEvent::WindowDestroyed(_) => Self::DESTROYED,
+            WindowEvent::Error(_) => Self::ERROR,
+            WindowEvent::Disconnected(_) | WindowEvent::Reconnected(_) => Self::CONNECTION,
+            WindowEvent::Idle { .. } | WindowEvent::Active { .. } => Self::IDLE,
+            WindowEvent::DesktopChanged(_) => Self::DESKTOP,
+            WindowEvent::StateChanged(_) => Self::STATE,
+        };
+        self.contains(kind)
+    }
+}
+
+impl Default for EventKinds {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl BitOr for EventKinds {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for EventKinds {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.insert(rhs);
+    }
+}
+
+/// Settings shared by the platform backends, see [`WindowFocusHookBuilder`](crate::WindowFocusHookBuilder).
+#[derive(Debug, Clone)]
+pub(crate) struct HookConfig {
+    /// X11 display names; empty uses `$DISPLAY`.
+    pub(crate) displays: Vec<String>,
+    pub(crate) dedup: DedupStrategy,
+    pub(crate) emit_initial: bool,
+    pub(crate) events: EventKinds,
+    /// How long focus must stay on a window before the change is reported.
+    pub(crate) settle_delay: Option<Duration>,
+    /// How long without input before the user counts as idle.
+    #[cfg(feature = "idle")]
+    pub(crate) idle_threshold: Option<Duration>,
+    pub(crate) filter: WindowFilter,
+    pub(crate) panic_policy: PanicPolicy,
+    /// Capacity and overflow policy of the dispatch queue; `None` runs
+    /// handlers on the event loop thread.
+    pub(crate) dispatch_queue: Option<(usize, OverflowPolicy)>,
+    /// `None` stops the hook with [`WinshiftError::ConnectionLost`](crate::WinshiftError::ConnectionLost).
+    pub(crate) reconnect: Option<ReconnectPolicy>,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            displays: Vec::new(),
+            dedup: DedupStrategy::default(),
+            emit_initial: true,
+            events: EventKinds::default(),
+            settle_delay: None,
+            #[cfg(feature = "idle")]
+            idle_threshold: None,
+            filter: WindowFilter::default(),
+            panic_policy: PanicPolicy::default(),
+            dispatch_queue: None,
+            reconnect: None,
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::config::PanicPolicy;
+use crate::error::WinshiftError;
+use crate::event::WindowEvent;
+use crate::FocusChangeHandler;
+use crate::{log_error, log_trace};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Identifies a handler registered with a [`WindowFocusHook`](crate::WindowFocusHook).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+type SharedHandler = Arc<RwLock<dyn FocusChangeHandler>>;
+
+pub(crate) struct Dispatcher {
+    handlers: RwLock<Vec<(HandlerId, SharedHandler)>>,
+    next_id: AtomicU64,
+    subscribers: Mutex<Vec<Sender<WindowEvent>>>,
+    panic_policy: PanicPolicy,
+    // Set when a handler panicked under PanicPolicy::StopHook
+    failure: Mutex<Option<WinshiftError>>,
+    // Set along with `failure` and kept until the hook runs again, so that no
+    // event reaches the handlers once the hook was asked to stop
+    stopped: AtomicBool,
+    dropped_events: AtomicU64,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(panic_policy: PanicPolicy) -> Self {
+        Self {
+            handlers: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            subscribers: Mutex::new(Vec::new()),
+            panic_policy,
+            failure: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            dropped_events: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record_dropped(&self, count: u64) {
+        if count > 0 {
+            self.dropped_events.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn has_failure(&self) -> bool {
+        self.failure.lock().is_ok_and(|failure| failure.is_some())
+    }
+
+    /// Lets events through again after a handler stopped the previous run.
+    pub(crate) fn resume(&self) {
+        self.stopped.store(false, Ordering::Release);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Takes the error that should stop the hook, if any.
+    pub(crate) fn take_failure(&self) -> Option<WinshiftError> {
+        self.failure.lock().ok()?.take()
+    }
+
+    pub(crate) fn add_handler(&self, handler: SharedHandler) -> HandlerId {
+        let id = HandlerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        if let Ok(mut handlers) = self.handlers.write() {
+            handlers.push((id, handler));
+        }
+        id
+    }
+
+    pub(crate) fn remove_handler(&self, id: HandlerId) -> bool {
+        match self.handlers.write() {
+            Ok(mut handlers) => {
+                let before = handlers.len();
+                handlers.retain(|(handler_id, _)| *handler_id != id);
+                handlers.len() != before
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn subscribe(&self, sender: Sender<WindowEvent>) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+    }
+
+    pub(crate) fn dispatch(&self, event: WindowEvent) {
+        if self.is_stopped() {
+            log_trace!("Hook is stopping, dropping event");
+            return;
+        }
+        // Snapshot the registry so handlers may add or remove handlers themselves
+        let handlers: Vec<(HandlerId, SharedHandler)> = match self.handlers.read() {
+            Ok(handlers) => handlers.clone(),
+            Err(_) => Vec::new(),
+        };
+
+        for (id, handler) in &handlers {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                if let Ok(guard) = handler.read() {
+                    match &event {
+                        WindowEvent::FocusChanged(event) => guard.on_focus_change(event),
+                        WindowEvent::TitleChanged(event) => guard.on_title_change(event),
+                        WindowEvent::WindowCreated(event) => guard.on_window_created(event),
+                        WindowEvent::WindowDestroyed(event) => guard.on_window_destroyed(event),
+                        WindowEvent::Error(error) => guard.on_error(error),
+                        WindowEvent::Disconnected(display) => guard.on_disconnect(display),
+                        WindowEvent::Reconnected(display) => guard.on_reconnect(display),
+                        WindowEvent::Idle { display, since } => guard.on_idle(display, *since),
+                        WindowEvent::Active { display } => guard.on_active(display),
+                        WindowEvent::DesktopChanged(event) => guard.on_desktop_change(event),
+                        WindowEvent::StateChanged(event) => guard.on_state_change(event),
+                    }
+                }
+            }));
+            if let Err(payload) = result {
+                self.handle_panic(*id, payload);
+                if self.is_stopped() {
+                    return;
+                }
+            }
+        }
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            // Drop subscribers whose receiver has gone away
+            subscribers.retain(|sender| sender.send(event.clone()).is_ok());
+            log_trace!(
+                "Event sent to {} handler(s) and {} subscriber(s)",
+                handlers.len(),
+                subscribers.len()
+            );
+        }
+    }
+
+    fn handle_panic(&self, id: HandlerId, payload: Box<dyn Any + Send>) {
+        let message = panic_message(payload.as_ref());
+        log_error!("Handler {:?} panicked: {}", id, message);
+        match self.panic_policy {
+            PanicPolicy::LogAndContinue => {}
+            PanicPolicy::DisableHandler => {
+                log_error!("Disabling handler {:?}", id);
+                self.remove_handler(id);
+            }
+            PanicPolicy::StopHook => {
+                if let Ok(mut failure) = self.failure.lock() {
+                    failure.get_or_insert(WinshiftError::HandlerPanicked(message));
+                }
+                self.stopped.store(true, Ordering::Release);
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    /// Counts calls and panics on every disconnect.
+    #[derive(Default)]
+    struct Panicking {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl FocusChangeHandler for Panicking {
+        fn on_focus_change(&self, _event: &crate::FocusEvent) {}
+
+        fn on_disconnect(&self, display: &str) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            panic!("lost {}", display);
+        }
+    }
+
+    fn dispatcher(policy: PanicPolicy) -> (Dispatcher, HandlerId, Arc<AtomicUsize>) {
+        let dispatcher = Dispatcher::new(policy);
+        let handler = Panicking::default();
+        let calls = handler.calls.clone();
+        let id = dispatcher.add_handler(Arc::new(RwLock::new(handler)));
+        (dispatcher, id, calls)
+    }
+
+    fn disconnected() -> WindowEvent {
+        WindowEvent::Disconnected(Arc::from(":0"))
+    }
+
+    #[test]
+    fn log_and_continue_keeps_the_handler() {
+        let (dispatcher, id, calls) = dispatcher(PanicPolicy::LogAndContinue);
+        dispatcher.dispatch(disconnected());
+        dispatcher.dispatch(disconnected());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(dispatcher.take_failure().is_none());
+        assert!(dispatcher.remove_handler(id));
+    }
+
+    #[test]
+    fn disable_handler_removes_the_handler() {
+        let (dispatcher, id, calls) = dispatcher(PanicPolicy::DisableHandler);
+        dispatcher.dispatch(disconnected());
+        dispatcher.dispatch(disconnected());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(dispatcher.take_failure().is_none());
+        assert!(!dispatcher.remove_handler(id));
+    }
+
+    #[test]
+    fn stop_hook_records_the_panic() {
+        let (dispatcher, _, calls) = dispatcher(PanicPolicy::StopHook);
+        let (sender, receiver) = mpsc::channel();
+        dispatcher.subscribe(sender);
+        dispatcher.dispatch(disconnected());
+        dispatcher.dispatch(disconnected());
+
+        // Nothing is delivered once the hook was asked to stop
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(receiver.try_recv().is_err());
+        assert!(dispatcher.has_failure());
+        match dispatcher.take_failure() {
+            Some(WinshiftError::HandlerPanicked(message)) => assert_eq!(message, "lost :0"),
+            other => panic!("unexpected failure: {:?}", other),
+        }
+        assert!(dispatcher.take_failure().is_none());
+
+        // The next run dispatches again
+        dispatcher.resume();
+        dispatcher.dispatch(disconnected());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(dispatcher.has_failure());
+    }
+}
@@ -1,15 +1,33 @@
+use std::io;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum WinshiftError {
-    #[error("Failed to initialize hook")]
-    InitializationError,
+    /// The X display could not be opened; `None` means `$DISPLAY` was used.
+    #[cfg(target_os = "linux")]
+    #[error("Failed to open X11 display {}", display.as_deref().unwrap_or("$DISPLAY"))]
+    DisplayOpen { display: Option<String> },
+
+    #[error("Interrupt pipe error")]
+    Pipe(#[source] io::Error),
+
+    #[error("Failed to spawn thread")]
+    Thread(#[source] io::Error),
+
+    #[error("I/O error")]
+    Io(#[from] io::Error),
 
-    #[error("Failed to set event hook")]
-    HookError,
+    #[error("Connection to the display server was lost")]
+    ConnectionLost,
 
-    #[error("Failed to stop hook")]
-    StopError,
+    #[error("Hook is not running")]
+    NotRunning,
+
+    #[error("Hook is already running")]
+    AlreadyRunning,
+
+    #[error("Handler panicked: {0}")]
+    HandlerPanicked(String),
 
     #[error("Platform-specific error: {0}")]
     PlatformError(String),
@@ -19,10 +37,20 @@ pub enum WinshiftError {
     WindowsError(#[from] windows::core::Error),
 
     #[cfg(target_os = "linux")]
-    #[error("X11 error: {0}")]
-    X11Error(String),
+    #[error("X11 protocol error {name} (code {error_code}) in request {request_code}.{minor_code} on resource {resource_id:#x}")]
+    XProtocol {
+        name: String,
+        error_code: u8,
+        request_code: u8,
+        minor_code: u8,
+        resource_id: u64,
+    },
+
+    #[cfg(target_os = "linux")]
+    #[error("Failed to read property {atom} of window {window:#x}")]
+    PropertyRead { window: u64, atom: String },
 
     #[cfg(target_os = "macos")]
     #[error("macOS error: {0}")]
     MacOSError(String),
-}
\ No newline at end of file
+}
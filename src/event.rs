@@ -0,0 +1,210 @@
+use crate::error::WinshiftError;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// Metadata describing a top-level window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// X11 window id.
+    pub id: u64,
+    /// Window title, read from `_NET_WM_NAME` with a `WM_NAME` fallback;
+    /// empty if the window has neither.
+    pub title: String,
+    /// Class part of `WM_CLASS`, e.g. `"firefox"`.
+    pub class: Option<String>,
+    /// Instance part of `WM_CLASS`, e.g. `"Navigator"`.
+    pub instance: Option<String>,
+    /// Owning process id from `_NET_WM_PID`, if the client sets it.
+    pub pid: Option<u32>,
+    /// Host the client runs on, from `WM_CLIENT_MACHINE`; `pid` refers to a
+    /// process on this host.
+    pub machine: Option<String>,
+    /// Desktop index from `_NET_WM_DESKTOP`; `0xFFFFFFFF` means all desktops.
+    pub desktop: Option<u32>,
+    /// State from `_NET_WM_STATE`, `WM_STATE` and the `WM_HINTS` urgency flag.
+    pub state: WindowState,
+}
+
+/// The state of a window, from the `_NET_WM_STATE` flags, ICCCM `WM_STATE`
+/// and the `WM_HINTS` urgency flag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowState {
+    pub modal: bool,
+    pub sticky: bool,
+    pub maximized_vert: bool,
+    pub maximized_horz: bool,
+    pub shaded: bool,
+    pub skip_taskbar: bool,
+    pub skip_pager: bool,
+    /// Set by the window manager while the window is minimized.
+    pub hidden: bool,
+    pub fullscreen: bool,
+    pub above: bool,
+    pub below: bool,
+    pub demands_attention: bool,
+    /// `WM_STATE` is `IconicState`.
+    pub iconic: bool,
+    /// The urgency flag of `WM_HINTS` is set.
+    pub urgent: bool,
+}
+
+impl WindowState {
+    /// Maximized both vertically and horizontally.
+    pub fn is_maximized(&self) -> bool {
+        self.maximized_vert && self.maximized_horz
+    }
+
+    /// Iconified, as reported by either `WM_STATE` or `_NET_WM_STATE_HIDDEN`.
+    pub fn is_minimized(&self) -> bool {
+        self.iconic || self.hidden
+    }
+
+    /// Asking for the user's attention through EWMH or ICCCM.
+    pub fn needs_attention(&self) -> bool {
+        self.demands_attention || self.urgent
+    }
+}
+
+/// A monitor reported by XRandR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// Monitor name, which for monitors the server creates itself is the
+    /// output name, e.g. `"DP-1"`.
+    pub name: String,
+    pub primary: bool,
+    /// Position relative to the root window.
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// An EWMH virtual desktop, also called workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Desktop {
+    /// Zero-based index, as in `_NET_CURRENT_DESKTOP`.
+    pub index: u32,
+    /// Name from `_NET_DESKTOP_NAMES`, if the window manager sets one.
+    pub name: Option<String>,
+}
+
+/// A switch to another virtual desktop.
+#[derive(Debug, Clone)]
+pub struct DesktopEvent {
+    pub desktop: Desktop,
+    /// The desktop shown before, if it was known.
+    pub previous: Option<Desktop>,
+    /// Number of desktops from `_NET_NUMBER_OF_DESKTOPS`.
+    pub count: Option<u32>,
+    /// Name of the X display the desktop belongs to.
+    pub display: Arc<str>,
+    /// Number of the screen the desktop belongs to.
+    pub screen: u32,
+}
+
+/// A change of the state of a managed window, e.g. entering fullscreen.
+#[derive(Debug, Clone)]
+pub struct StateEvent {
+    /// X11 window id.
+    pub window: u64,
+    pub previous: WindowState,
+    pub state: WindowState,
+    /// Name of the X display the window is on.
+    pub display: Arc<str>,
+    /// Number of the screen the window is on.
+    pub screen: u32,
+}
+
+/// A change of the focused window.
+#[derive(Debug, Clone)]
+pub struct FocusEvent {
+    /// The window that gained focus.
+    pub window: WindowInfo,
+    /// The window that was focused before, if any.
+    pub previous_window: Option<u64>,
+    /// Monotonic time at which the change was observed.
+    pub timestamp: Instant,
+    /// Wall-clock time at which the change was observed.
+    pub wall_time: SystemTime,
+    /// `true` for the synthetic event reporting the window that was already
+    /// focused when the hook started.
+    pub initial: bool,
+    /// Name of the X display the window is on, e.g. `":0"`.
+    pub display: Arc<str>,
+    /// Number of the screen the window is on; `previous_window` refers to
+    /// the same screen.
+    pub screen: u32,
+    /// The monitor showing most of the window; needs the `xrandr` feature.
+    pub monitor: Option<Monitor>,
+    /// The desktop shown when the event was observed, if the window manager
+    /// reports it. See [`WindowInfo::desktop`] for the window's own desktop.
+    pub desktop: Option<Desktop>,
+}
+
+impl FocusEvent {
+    pub(crate) fn new(
+        window: WindowInfo,
+        previous_window: Option<u64>,
+        display: Arc<str>,
+        screen: u32,
+    ) -> Self {
+        Self {
+            window,
+            previous_window,
+            timestamp: Instant::now(),
+            wall_time: SystemTime::now(),
+            initial: false,
+            display,
+            screen,
+            monitor: None,
+            desktop: None,
+        }
+    }
+}
+
+/// The creation or destruction of a top-level window.
+#[derive(Debug, Clone)]
+pub struct LifecycleEvent {
+    /// X11 window id.
+    pub window: u64,
+    /// Name of the X display the window is on.
+    pub display: Arc<str>,
+    /// Number of the screen the window is on.
+    pub screen: u32,
+}
+
+/// An event delivered to subscribers of a [`WindowFocusHook`](crate::WindowFocusHook).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum WindowEvent {
+    /// A different window gained focus.
+    FocusChanged(FocusEvent),
+    /// The title of the focused window changed.
+    TitleChanged(FocusEvent),
+    /// A top-level window was created: a child of a root window that is not
+    /// override-redirect, so menus and tooltips are left out. Under a
+    /// reparenting window manager the frame is such a window as well.
+    WindowCreated(LifecycleEvent),
+    /// A top-level window was destroyed.
+    WindowDestroyed(LifecycleEvent),
+    /// The hook hit an error it recovered from, such as an X protocol error.
+    Error(Arc<WinshiftError>),
+    /// The connection to the named display was lost.
+    Disconnected(Arc<str>),
+    /// The hook connected to the named display again after [`WindowEvent::Disconnected`].
+    Reconnected(Arc<str>),
+    /// Nobody has used keyboard or mouse on `display` since `since`.
+    ///
+    /// Only reported with the `idle` feature, see
+    /// [`WindowFocusHookBuilder::idle_threshold`](crate::WindowFocusHookBuilder).
+    Idle {
+        display: Arc<str>,
+        since: SystemTime,
+    },
+    /// Input on `display` resumed after [`WindowEvent::Idle`].
+    Active { display: Arc<str> },
+    /// The window manager switched to another virtual desktop.
+    DesktopChanged(DesktopEvent),
+    /// A managed window changed state, e.g. was maximized or asks for attention.
+    StateChanged(StateEvent),
+}
@@ -0,0 +1,219 @@
+use crate::event::WindowInfo;
+use regex::Regex;
+
+/// A condition on a window, used by [`WindowFilter`].
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Matches the window title.
+    Title(Regex),
+    /// Matches either part of `WM_CLASS`, the class or the instance.
+    Class(Regex),
+    /// Matches the owning process id.
+    Pid(u32),
+    /// Matches the path of the owning process's executable.
+    ///
+    /// The process is looked up on the local machine, so windows whose
+    /// `WM_CLIENT_MACHINE` names another host never match.
+    Executable(Regex),
+}
+
+impl Rule {
+    pub fn title(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self::Title)
+    }
+
+    pub fn class(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self::Class)
+    }
+
+    pub fn pid(pid: u32) -> Self {
+        Self::Pid(pid)
+    }
+
+    pub fn executable(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self::Executable)
+    }
+
+    fn matches(&self, window: &WindowInfo) -> bool {
+        match self {
+            Self::Title(regex) => regex.is_match(&window.title),
+            Self::Class(regex) => [&window.class, &window.instance]
+                .into_iter()
+                .flatten()
+                .any(|value| regex.is_match(value)),
+            Self::Pid(pid) => window.pid == Some(*pid),
+            Self::Executable(regex) => {
+                executable_path(window).is_some_and(|path| regex.is_match(&path))
+            }
+        }
+    }
+}
+
+/// Decides which windows a hook reports.
+///
+/// A window passes if it matches any include rule (or there are none) and no
+/// exclude rule. Windows that do not pass are invisible to the hook: focusing
+/// one is not reported and does not count as the last focused window.
+///
+/// Only focus, title and state events are filtered. Window creation and
+/// destruction are always reported, since a new window has no title or class
+/// to match yet.
+#[derive(Debug, Clone, Default)]
+pub struct WindowFilter {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+}
+
+impl WindowFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(mut self, rule: Rule) -> Self {
+        self.include.push(rule);
+        self
+    }
+
+    pub fn exclude(mut self, rule: Rule) -> Self {
+        self.exclude.push(rule);
+        self
+    }
+
+    /// Returns `true` if the window passes the filter.
+    pub fn allows(&self, window: &WindowInfo) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|rule| rule.matches(window)))
+            && !self.exclude.iter().any(|rule| rule.matches(window))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn executable_path(window: &WindowInfo) -> Option<String> {
+    // _NET_WM_PID is a process id on the client's host, e.g. over ssh -X
+    if let Some(machine) = &window.machine {
+        if !local_hostname().is_some_and(|hostname| same_host(machine, hostname)) {
+            return None;
+        }
+    }
+    std::fs::read_link(format!("/proc/{}/exe", window.pid?))
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn executable_path(_window: &WindowInfo) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn local_hostname() -> Option<&'static str> {
+    static HOSTNAME: std::sync::OnceLock<Option<String>> = std::sync::OnceLock::new();
+    HOSTNAME
+        .get_or_init(|| {
+            let mut buf = [0u8; 256];
+            if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+                return None;
+            }
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+        })
+        .as_deref()
+}
+
+/// Compares host names, ignoring the domain since clients may set either
+/// the short or the fully qualified name.
+#[cfg(target_os = "linux")]
+fn same_host(a: &str, b: &str) -> bool {
+    let short = |name: &str| name.split('.').next().unwrap_or(name).to_ascii_lowercase();
+    short(a) == short(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::WindowState;
+
+    fn window(title: &str, class: &str) -> WindowInfo {
+        WindowInfo {
+            id: 1,
+            title: title.to_string(),
+            class: Some(class.to_string()),
+            instance: None,
+            pid: None,
+            machine: None,
+            desktop: None,
+            state: WindowState::default(),
+        }
+    }
+
+    #[test]
+    fn empty_filter_allows_everything() {
+        assert!(WindowFilter::new().allows(&window("Terminal", "xterm")));
+    }
+
+    #[test]
+    fn include_rules_are_alternatives() {
+        let filter = WindowFilter::new()
+            .include(Rule::class("^firefox$").unwrap())
+            .include(Rule::title("Editor").unwrap());
+        assert!(filter.allows(&window("Mozilla", "firefox")));
+        assert!(filter.allows(&window("Text Editor", "gedit")));
+        assert!(!filter.allows(&window("Terminal", "xterm")));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = WindowFilter::new()
+            .include(Rule::class("firefox").unwrap())
+            .exclude(Rule::title("Private").unwrap());
+        assert!(filter.allows(&window("Mozilla", "firefox")));
+        assert!(!filter.allows(&window("Private Browsing", "firefox")));
+    }
+
+    #[test]
+    fn exclude_only_allows_the_rest() {
+        let filter = WindowFilter::new().exclude(Rule::class("xterm").unwrap());
+        assert!(!filter.allows(&window("Terminal", "xterm")));
+        assert!(filter.allows(&window("Mozilla", "firefox")));
+    }
+
+    #[test]
+    fn class_matches_instance_too() {
+        let mut info = window("Mozilla", "firefox");
+        info.instance = Some("Navigator".to_string());
+        let filter = WindowFilter::new().include(Rule::class("^Navigator$").unwrap());
+        assert!(filter.allows(&info));
+    }
+
+    #[test]
+    fn pid_rule_needs_a_pid() {
+        let filter = WindowFilter::new().include(Rule::pid(42));
+        let mut info = window("Terminal", "xterm");
+        assert!(!filter.allows(&info));
+        info.pid = Some(42);
+        assert!(filter.allows(&info));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn executable_is_only_looked_up_for_local_clients() {
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_name().unwrap().to_string_lossy();
+        let filter = WindowFilter::new().include(Rule::executable(&regex::escape(&name)).unwrap());
+
+        let mut info = window("Test", "test");
+        info.pid = Some(std::process::id());
+        assert!(filter.allows(&info));
+        info.machine = local_hostname().map(str::to_string);
+        assert!(filter.allows(&info));
+        info.machine = Some("another-host.invalid".to_string());
+        assert!(!filter.allows(&info));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn host_names_compare_without_domain() {
+        assert!(same_host("box", "box.example.org"));
+        assert!(same_host("BOX.example.org", "box"));
+        assert!(!same_host("box", "other"));
+    }
+}
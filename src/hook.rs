@@ -1,45 +1,218 @@
+use crate::config::{
+    DedupStrategy, EventKinds, HookConfig, OverflowPolicy, PanicPolicy, ReconnectPolicy,
+};
+use crate::dispatch::{Dispatcher, HandlerId};
 use crate::error::WinshiftError;
+use crate::event::{DesktopEvent, FocusEvent, LifecycleEvent, StateEvent, WindowEvent};
+use crate::filter::WindowFilter;
 use crate::{log_debug, log_error, log_trace};
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
 
 pub trait FocusChangeHandler: Send + Sync {
-    fn on_focus_change(&self, window_title: String);
+    /// Called when a different window gains focus.
+    fn on_focus_change(&self, event: &FocusEvent);
+
+    /// Called when the title of the focused window changes without a focus change.
+    fn on_title_change(&self, _event: &FocusEvent) {}
+
+    /// Called when a top-level window is created, see
+    /// [`WindowEvent::WindowCreated`].
+    fn on_window_created(&self, _event: &LifecycleEvent) {}
+
+    /// Called when a top-level window is destroyed.
+    fn on_window_destroyed(&self, _event: &LifecycleEvent) {}
+
+    /// Called when the hook hits an error it keeps running through, such as
+    /// an X protocol error or a property that could not be read.
+    fn on_error(&self, _error: &WinshiftError) {}
+
+    /// Called when the connection to `display` is lost.
+    fn on_disconnect(&self, _display: &str) {}
+
+    /// Called when the hook has reconnected to `display` after a disconnect.
+    fn on_reconnect(&self, _display: &str) {}
+
+    /// Called when nobody has used `display` for the idle threshold; `since`
+    /// is when input stopped.
+    fn on_idle(&self, _display: &str, _since: SystemTime) {}
+
+    /// Called on the first input on `display` after [`on_idle`](Self::on_idle).
+    fn on_active(&self, _display: &str) {}
+
+    /// Called when the window manager switches to another virtual desktop.
+    fn on_desktop_change(&self, _event: &DesktopEvent) {}
+
+    /// Called when a managed window changes state, e.g. enters fullscreen,
+    /// is maximized, minimized or restored, or asks for attention.
+    fn on_state_change(&self, _event: &StateEvent) {}
 }
 
+/// Closures taking a [`FocusEvent`] handle focus changes.
+impl<F> FocusChangeHandler for F
+where
+    F: Fn(&FocusEvent) + Send + Sync,
+{
+    fn on_focus_change(&self, event: &FocusEvent) {
+        self(event)
+    }
+}
+
+/// Watches the focused window and reports changes to handlers and to subscribers.
+///
+/// A hook created with [`Default`] starts without handlers; they can be
+/// attached and detached at any time, including while the hook is running.
 pub struct WindowFocusHook {
-    handler: Arc<RwLock<dyn FocusChangeHandler>>,
+    dispatcher: Arc<Dispatcher>,
+    config: HookConfig,
+    #[cfg(target_os = "linux")]
+    state: crate::linux::HookState,
+}
+
+impl Default for WindowFocusHook {
+    fn default() -> Self {
+        log_debug!("Creating new WindowFocusHook without handler");
+        Self::with_config(HookConfig::default())
+    }
 }
 
 impl WindowFocusHook {
     pub fn new<H: FocusChangeHandler + 'static>(handler: H) -> Self {
         log_debug!("Creating new WindowFocusHook");
+        let hook = Self::default();
+        hook.add_handler(handler);
+        hook
+    }
+
+    fn with_config(config: HookConfig) -> Self {
         Self {
-            handler: Arc::new(RwLock::new(handler)),
+            dispatcher: Arc::new(Dispatcher::new(config.panic_policy)),
+            config,
+            #[cfg(target_os = "linux")]
+            state: crate::linux::HookState::default(),
         }
     }
 
+    /// Returns a builder for a hook with non-default settings.
+    pub fn builder() -> WindowFocusHookBuilder {
+        WindowFocusHookBuilder::default()
+    }
+
+    /// Creates a hook that calls `f` on every focus change.
+    ///
+    /// Equivalent to [`new`](Self::new), but lets the compiler infer the
+    /// closure's argument type.
+    pub fn from_fn<F>(f: F) -> Self
+    where
+        F: Fn(&FocusEvent) + Send + Sync + 'static,
+    {
+        Self::new(f)
+    }
+
+    /// Registers another handler; every handler receives every event.
+    pub fn add_handler<H: FocusChangeHandler + 'static>(&self, handler: H) -> HandlerId {
+        let id = self.dispatcher.add_handler(Arc::new(RwLock::new(handler)));
+        log_debug!("Added handler {:?}", id);
+        id
+    }
+
+    /// Unregisters a handler. Returns `false` if it was not registered.
+    pub fn remove_handler(&self, id: HandlerId) -> bool {
+        let removed = self.dispatcher.remove_handler(id);
+        log_debug!("Removing handler {:?}: {}", id, removed);
+        removed
+    }
+
+    /// Number of events dropped because the dispatch queue was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.dispatcher.dropped_events()
+    }
+
+    /// Returns a receiver for every event the hook reports from now on.
+    ///
+    /// Subscribe first, then start the hook with [`spawn`](Self::spawn) so it
+    /// keeps running on its own thread while the receivers consume events.
+    /// Any number of receivers may be created, and dropping one simply
+    /// unsubscribes it.
+    pub fn subscribe(&self) -> Receiver<WindowEvent> {
+        log_debug!("Adding WindowFocusHook subscriber");
+        let (sender, receiver) = mpsc::channel();
+        self.dispatcher.subscribe(sender);
+        receiver
+    }
+
     pub fn run(&self) -> Result<(), WinshiftError> {
+        self.run_with(|| {})
+    }
+
+    /// Runs the hook on a background thread.
+    ///
+    /// Returns once the event loop is running, or with the error that kept it
+    /// from starting. The returned guard stops the hook and joins the thread
+    /// when dropped; receivers from [`subscribe`](Self::subscribe) get events
+    /// until then.
+    pub fn spawn(self) -> Result<HookGuard, WinshiftError> {
+        log_debug!("Spawning WindowFocusHook thread");
+        let hook = Arc::new(self);
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let thread = {
+            let hook = hook.clone();
+            thread::Builder::new()
+                .name("winshift-hook".to_string())
+                .spawn(move || {
+                    hook.run_with(move || {
+                        let _ = ready_sender.send(());
+                    })
+                })
+                .map_err(|e| {
+                    log_error!("Failed to spawn hook thread: {}", e);
+                    WinshiftError::Thread(e)
+                })?
+        };
+
+        if ready_receiver.recv().is_err() {
+            // The sender was dropped without signalling, so the loop never started
+            log_debug!("Hook thread exited before the event loop started");
+            return match thread.join() {
+                Ok(Ok(())) => Err(WinshiftError::NotRunning),
+                Ok(Err(e)) => Err(e),
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+        }
+
+        log_debug!("Hook thread running");
+        Ok(HookGuard {
+            hook,
+            thread: Some(thread),
+        })
+    }
+
+    fn run_with(&self, on_ready: impl FnOnce()) -> Result<(), WinshiftError> {
         log_debug!("Running WindowFocusHook");
+        self.dispatcher.resume();
         #[cfg(target_os = "windows")]
         {
             log_trace!("Running on Windows platform");
-            crate::windows::run_hook(self.handler.clone())
+            crate::windows::run_hook(self.dispatcher.clone(), on_ready)
         }
 
         #[cfg(target_os = "linux")]
         {
             log_trace!("Running on Linux platform");
-            crate::linux::run_hook(self.handler.clone())
+            crate::linux::run_hook(&self.state, &self.config, self.dispatcher.clone(), on_ready)
         }
 
         #[cfg(target_os = "macos")]
         {
             log_trace!("Running on macOS platform");
-            crate::macos::run_hook(self.handler.clone())
+            crate::macos::run_hook(self.dispatcher.clone(), on_ready)
         }
 
         #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
         {
+            let _ = on_ready;
             log_error!("Unsupported platform");
             Err(WinshiftError::PlatformError(
                 "Unsupported platform".to_string(),
@@ -58,7 +231,7 @@ impl WindowFocusHook {
         #[cfg(target_os = "linux")]
         {
             log_trace!("Stopping on Linux platform");
-            crate::linux::stop_hook()
+            crate::linux::stop_hook(&self.state)
         }
 
         #[cfg(target_os = "macos")]
@@ -76,3 +249,192 @@ impl WindowFocusHook {
         }
     }
 }
+
+/// Configures a [`WindowFocusHook`], see [`WindowFocusHook::builder`].
+#[derive(Default)]
+pub struct WindowFocusHookBuilder {
+    config: HookConfig,
+    handlers: Vec<Arc<RwLock<dyn FocusChangeHandler>>>,
+}
+
+impl WindowFocusHookBuilder {
+    /// Connects to the given X11 display, e.g. `":1"`, instead of `$DISPLAY`.
+    pub fn display(mut self, name: impl Into<String>) -> Self {
+        self.config.displays = vec![name.into()];
+        self
+    }
+
+    /// Watches every given X11 display from the same hook.
+    ///
+    /// Events carry the name of the display they came from. The hook fails to
+    /// start if any of the displays cannot be opened.
+    pub fn displays<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.displays = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether title changes of the focused window are reported. Enabled by default.
+    pub fn emit_title_changes(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.config.events.insert(EventKinds::TITLE);
+        } else {
+            self.config.events.remove(EventKinds::TITLE);
+        }
+        self
+    }
+
+    /// How repeated focus changes are filtered. Defaults to [`DedupStrategy::Title`].
+    pub fn dedup(mut self, strategy: DedupStrategy) -> Self {
+        self.config.dedup = strategy;
+        self
+    }
+
+    /// Whether the window focused when the hook starts is reported as a focus
+    /// change with [`FocusEvent::initial`] set. Enabled by default.
+    pub fn emit_initial(mut self, enabled: bool) -> Self {
+        self.config.emit_initial = enabled;
+        self
+    }
+
+    /// The kinds of events to report. Defaults to [`EventKinds::ALL`].
+    pub fn events(mut self, events: EventKinds) -> Self {
+        self.config.events = events;
+        self
+    }
+
+    /// Only reports focus that stays on a window for at least `delay`.
+    ///
+    /// Windows passed through quicker, e.g. while alt-tabbing, are dropped and
+    /// the next reported event's `previous_window` is the last reported window.
+    /// Disabled by default.
+    pub fn settle_delay(mut self, delay: Duration) -> Self {
+        self.config.settle_delay = Some(delay);
+        self
+    }
+
+    /// Reports [`WindowEvent::Idle`](crate::WindowEvent::Idle) once there has
+    /// been no input for `threshold`, and [`WindowEvent::Active`](crate::WindowEvent::Active)
+    /// when input resumes.
+    ///
+    /// Needs the MIT-SCREEN-SAVER extension on the X server; without it no idle
+    /// events are reported.
+    #[cfg(feature = "idle")]
+    pub fn idle_threshold(mut self, threshold: Duration) -> Self {
+        self.config.idle_threshold = Some(threshold);
+        self
+    }
+
+    /// Only reports windows that pass `filter`.
+    pub fn filter(mut self, filter: WindowFilter) -> Self {
+        self.config.filter = filter;
+        self
+    }
+
+    /// What happens when a handler panics. Defaults to [`PanicPolicy::LogAndContinue`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.config.panic_policy = policy;
+        self
+    }
+
+    /// Reconnects with backoff when the display connection drops, instead of
+    /// stopping with [`WinshiftError::ConnectionLost`].
+    ///
+    /// Handlers are told through [`FocusChangeHandler::on_disconnect`] and
+    /// [`FocusChangeHandler::on_reconnect`]. Streams always end on disconnect.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// Runs handlers on a separate thread, fed through a queue of `capacity` events.
+    ///
+    /// Keeps slow handlers from stalling X event processing. Events dropped
+    /// because of `overflow` are counted in [`WindowFocusHook::dropped_events`].
+    pub fn dispatch_queue(mut self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.config.dispatch_queue = Some((capacity, overflow));
+        self
+    }
+
+    /// Registers a handler, see [`WindowFocusHook::add_handler`].
+    pub fn handler<H: FocusChangeHandler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.push(Arc::new(RwLock::new(handler)));
+        self
+    }
+
+    pub fn build(self) -> WindowFocusHook {
+        log_debug!("Building WindowFocusHook with {:?}", self.config);
+        let hook = WindowFocusHook::with_config(self.config);
+        for handler in self.handlers {
+            hook.dispatcher.add_handler(handler);
+        }
+        hook
+    }
+
+    /// Builds a [`FocusStream`](crate::FocusStream) with these settings instead of a hook.
+    ///
+    /// Handlers registered on the builder are not called by the stream.
+    #[cfg(all(target_os = "linux", feature = "tokio"))]
+    pub fn build_stream(self) -> Result<crate::FocusStream, WinshiftError> {
+        crate::FocusStream::with_config(self.config)
+    }
+}
+
+/// A [`WindowFocusHook`] running on a background thread, see [`WindowFocusHook::spawn`].
+///
+/// Dropping the guard stops the hook and waits for the thread to finish.
+pub struct HookGuard {
+    hook: Arc<WindowFocusHook>,
+    thread: Option<JoinHandle<Result<(), WinshiftError>>>,
+}
+
+impl HookGuard {
+    /// The running hook, e.g. to add handlers or subscribers.
+    pub fn hook(&self) -> &WindowFocusHook {
+        &self.hook
+    }
+
+    /// Returns `true` if the event loop has exited on its own.
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    /// Stops the hook and returns the result of its event loop.
+    pub fn join(mut self) -> Result<(), WinshiftError> {
+        match self.stop_and_join() {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+
+    fn stop_and_join(&mut self) -> Option<thread::Result<Result<(), WinshiftError>>> {
+        let thread = self.thread.take()?;
+        if !thread.is_finished() {
+            if let Err(e) = self.hook.stop() {
+                log_error!("Error stopping hook: {}", e);
+            }
+        }
+        log_trace!("Joining hook thread");
+        Some(thread.join())
+    }
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        match self.stop_and_join() {
+            Some(Ok(Err(e))) => {
+                log_error!("Hook exited with error: {}", e);
+            }
+            Some(Err(_)) => {
+                log_error!("Hook thread panicked");
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,6 +1,12 @@
+mod config;
+mod dispatch;
 mod error;
+mod event;
+mod filter;
 mod hook;
 pub mod logger;
+mod query;
+mod queue;
 
 // #[cfg(target_os = "windows")]
 // mod windows;
@@ -8,11 +14,26 @@ pub mod logger;
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+mod stream;
+
 // #[cfg(target_os = "macos")]
 // mod macos;
 
+pub use config::{DedupStrategy, EventKinds, OverflowPolicy, PanicPolicy, ReconnectPolicy};
+pub use dispatch::HandlerId;
 pub use error::WinshiftError;
-pub use hook::{FocusChangeHandler, WindowFocusHook};
+pub use event::{
+    Desktop, DesktopEvent, FocusEvent, LifecycleEvent, Monitor, StateEvent, WindowEvent,
+    WindowInfo, WindowState,
+};
+pub use filter::{Rule, WindowFilter};
+pub use hook::{FocusChangeHandler, HookGuard, WindowFocusHook, WindowFocusHookBuilder};
+#[cfg(feature = "xrandr")]
+pub use query::current_monitor;
+pub use query::{current_focus, list_windows};
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+pub use stream::FocusStream;
 
 pub fn init_logger() {
     logger::init();
@@ -1,214 +1,1439 @@
+use crate::config::{DedupStrategy, EventKinds, HookConfig, ReconnectPolicy};
+use crate::dispatch::Dispatcher;
 use crate::error::WinshiftError;
-use crate::FocusChangeHandler;
+use crate::event::{
+    Desktop, DesktopEvent, FocusEvent, LifecycleEvent, Monitor, StateEvent, WindowEvent,
+    WindowInfo, WindowState,
+};
+use crate::queue::{spawn_dispatcher, EventQueue};
 use crate::{log_debug, log_error, log_info, log_trace, log_warn};
-use libc::{c_char, c_int, c_uchar, c_ulong, c_void, close, pipe, read, write, EINTR};
+use libc::{c_char, c_int, c_long, c_uchar, c_ulong, c_void, pipe2, read, write, EINTR, O_CLOEXEC};
 use libc::{fd_set, select, FD_SET, FD_ZERO};
-use std::ffi::CStr;
-use std::os::unix::io::RawFd;
-use std::sync::{Arc, RwLock};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, OnceLock};
+#[cfg(feature = "idle")]
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
 use x11::xlib;
+#[cfg(feature = "xrandr")]
+use x11::xrandr;
+#[cfg(feature = "idle")]
+use x11::xss;
 
-static mut INTERRUPT_PIPE: [RawFd; 2] = [-1, -1];
+/// Per-hook state shared between `run_hook` and `stop_hook`.
+#[derive(Default)]
+pub(crate) struct HookState {
+    // Write end of the self-pipe, present while the event loop is running
+    interrupt: Mutex<Option<OwnedFd>>,
+}
+
+impl HookState {
+    /// A second handle on the write end of the self-pipe.
+    fn wake_fd(&self) -> Result<OwnedFd, WinshiftError> {
+        let interrupt = self.interrupt.lock().unwrap_or_else(|e| e.into_inner());
+        let fd = interrupt.as_ref().ok_or(WinshiftError::NotRunning)?;
+        fd.try_clone().map_err(|e| {
+            log_error!("Failed to duplicate interrupt pipe: {}", e);
+            WinshiftError::Pipe(e)
+        })
+    }
 
-pub(crate) fn run_hook(handler: Arc<RwLock<dyn FocusChangeHandler>>) -> Result<(), WinshiftError> {
+    fn clear_interrupt(&self) {
+        self.interrupt
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+}
+
+pub(crate) fn run_hook(
+    hook_state: &HookState,
+    config: &HookConfig,
+    dispatcher: Arc<Dispatcher>,
+    on_ready: impl FnOnce(),
+) -> Result<(), WinshiftError> {
     log_debug!("Starting Linux hook");
+    // Create the self-pipe
+    let (interrupt_read, interrupt_write) = create_pipe()?;
+    log_trace!("Interrupt pipe created");
+
+    {
+        let mut interrupt = hook_state
+            .interrupt
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if interrupt.is_some() {
+            log_error!("Hook is already running");
+            return Err(WinshiftError::AlreadyRunning);
+        }
+        *interrupt = Some(interrupt_write);
+    }
+
+    // Only connect once this hook is known not to be running already
+    let mut connections =
+        Connection::open_all(config).inspect_err(|_| hook_state.clear_interrupt())?;
+
+    // Optionally hand events to a dispatcher thread instead of running handlers here
+    let mut worker = None;
+    if let Some((capacity, overflow)) = config.dispatch_queue {
+        let queue = Arc::new(EventQueue::new(capacity, overflow));
+        let wake = hook_state
+            .wake_fd()
+            .inspect_err(|_| hook_state.clear_interrupt())?;
+        let thread = spawn_dispatcher(queue.clone(), dispatcher.clone(), move || {
+            // Wake the event loop so it notices the failure
+            let buf = [0u8; 1];
+            unsafe { write(wake.as_raw_fd(), buf.as_ptr() as *const c_void, 1) };
+        })
+        .map_err(|e| {
+            log_error!("Failed to spawn dispatcher thread: {}", e);
+            hook_state.clear_interrupt();
+            WinshiftError::Thread(e)
+        })?;
+        worker = Some((queue, thread));
+    }
+    let queue = worker.as_ref().map(|(queue, _)| queue.clone());
+    let dispatch = |event: WindowEvent| match &queue {
+        Some(queue) => dispatcher.record_dropped(queue.push(event)),
+        None => dispatcher.dispatch(event),
+    };
+
+    // From here on stop_hook() can reach the loop
+    on_ready();
+    for connection in &mut connections {
+        connection.start(&mut |event| dispatch(event));
+    }
+    let mut result = dispatcher.take_failure().map_or(Ok(()), Err);
+
+    let interrupt_fd = interrupt_read.as_raw_fd();
+    while result.is_ok() {
+        let x11_fds: Vec<RawFd> = connections
+            .iter()
+            .filter_map(|connection| connection.session.as_ref().map(Session::as_raw_fd))
+            .collect();
+        // Wake up in time to report settled focus changes and to reconnect
+        let deadline = connections
+            .iter()
+            .filter_map(Connection::next_deadline)
+            .min();
+
+        log_trace!("Waiting for X11 events or interrupt signal");
+        if let Wait::Interrupted = wait(&x11_fds, interrupt_fd, deadline) {
+            break;
+        }
+
+        let now = Instant::now();
+        for connection in &mut connections {
+            if let Err(e) = connection.poll(config, now, &mut |event| dispatch(event)) {
+                result = Err(e);
+                break;
+            }
+        }
+
+        if let Some(e) = dispatcher.take_failure() {
+            log_error!("Stopping hook: {}", e);
+            result = Err(e);
+        }
+    }
+
+    // Let the dispatcher thread deliver what is still queued
+    if let Some((queue, thread)) = worker {
+        queue.close();
+        if thread.join().is_err() {
+            log_error!("Dispatcher thread panicked");
+        }
+        if let Some(e) = dispatcher.take_failure() {
+            result = result.and(Err(e));
+        }
+    }
+
+    // Close the self-pipe; stop_hook() holds the lock while writing to it
+    hook_state.clear_interrupt();
+    drop(interrupt_read);
+    log_trace!("Interrupt pipe closed");
+    drop(connections);
+
+    log_debug!("Linux hook stopped");
+    result
+}
+
+enum Wait {
+    Interrupted,
+    Ready,
+}
+
+/// Blocks until one of the X11 connections is readable, the hook is
+/// interrupted or `deadline` passes.
+fn wait(x11_fds: &[RawFd], interrupt_fd: RawFd, deadline: Option<Instant>) -> Wait {
     unsafe {
-        // Create the self-pipe
-        if pipe(INTERRUPT_PIPE.as_mut_ptr()) != 0 {
-            log_error!("Failed to create interrupt pipe");
-            return Err(WinshiftError::InitializationError);
+        let mut read_fds: fd_set = std::mem::zeroed();
+        FD_ZERO(&mut read_fds);
+        FD_SET(interrupt_fd, &mut read_fds);
+        for &fd in x11_fds {
+            FD_SET(fd, &mut read_fds);
+        }
+        let max_fd = x11_fds.iter().copied().fold(interrupt_fd, RawFd::max) + 1;
+
+        let mut timeout = deadline.map(|deadline| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            libc::timeval {
+                tv_sec: remaining.as_secs() as libc::time_t,
+                tv_usec: remaining.subsec_micros() as libc::suseconds_t,
+            }
+        });
+
+        let ready = select(
+            max_fd,
+            &mut read_fds,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            timeout
+                .as_mut()
+                .map_or(std::ptr::null_mut(), |timeout| timeout as *mut _),
+        );
+        if ready > 0 && libc::FD_ISSET(interrupt_fd, &read_fds) {
+            log_debug!("Received interrupt signal");
+            let mut buf = [0u8; 1];
+            read(interrupt_fd, buf.as_mut_ptr() as *mut c_void, 1);
+            return Wait::Interrupted;
+        } else if ready < 0 && *libc::__errno_location() != EINTR {
+            log_warn!("select() failed");
         }
-        log_trace!("Interrupt pipe created");
+        Wait::Ready
+    }
+}
+
+/// One display watched by a hook.
+struct Connection {
+    // Requested name; `None` for $DISPLAY
+    display: Option<String>,
+    // `None` while waiting to reconnect
+    session: Option<Session>,
+    backoff: Option<Backoff>,
+}
 
-        let display = xlib::XOpenDisplay(std::ptr::null());
-        if display.is_null() {
-            log_error!("Failed to open X11 display");
-            close(INTERRUPT_PIPE[0]);
-            close(INTERRUPT_PIPE[1]);
-            return Err(WinshiftError::InitializationError);
+impl Connection {
+    fn open_all(config: &HookConfig) -> Result<Vec<Self>, WinshiftError> {
+        let displays: Vec<Option<String>> = if config.displays.is_empty() {
+            vec![None]
+        } else {
+            config.displays.iter().cloned().map(Some).collect()
+        };
+        displays
+            .into_iter()
+            .map(|display| {
+                let session = Session::open(display.as_deref(), config.clone())?;
+                Ok(Self {
+                    display,
+                    session: Some(session),
+                    backoff: None,
+                })
+            })
+            .collect()
+    }
+
+    fn start(&mut self, emit: &mut dyn FnMut(WindowEvent)) {
+        if let Some(session) = &mut self.session {
+            session.start(emit);
         }
-        log_debug!("X11 display opened successfully");
+    }
 
-        let root = xlib::XDefaultRootWindow(display);
-        xlib::XSelectInput(
-            display,
-            root,
-            xlib::PropertyChangeMask | xlib::SubstructureNotifyMask,
+    fn next_deadline(&self) -> Option<Instant> {
+        match (&self.session, &self.backoff) {
+            (Some(session), _) => session.next_deadline(),
+            (None, Some(backoff)) => Some(backoff.next_attempt),
+            (None, None) => None,
+        }
+    }
+
+    /// Handles pending events, or tries to reconnect once the backoff has passed.
+    fn poll(
+        &mut self,
+        config: &HookConfig,
+        now: Instant,
+        emit: &mut dyn FnMut(WindowEvent),
+    ) -> Result<(), WinshiftError> {
+        let mut emit_if_wanted = |event: WindowEvent| {
+            if config.events.matches(&event) {
+                emit(event)
+            }
+        };
+
+        if let Some(session) = &mut self.session {
+            session.process_pending(&mut emit_if_wanted);
+            session.flush_due(now, &mut emit_if_wanted);
+            if session.is_lost() {
+                let name = session.name();
+                self.session = None;
+                let policy = config.reconnect.ok_or(WinshiftError::ConnectionLost)?;
+                let backoff = Backoff::new(policy).ok_or(WinshiftError::ConnectionLost)?;
+                emit_if_wanted(WindowEvent::Disconnected(name));
+                self.backoff = Some(backoff);
+            }
+            return Ok(());
+        }
+
+        let Some(retry) = &mut self.backoff else {
+            return Ok(());
+        };
+        if now < retry.next_attempt {
+            return Ok(());
+        }
+        log_info!(
+            "Reconnecting to X11 display (attempt {})",
+            retry.attempts + 1
+        );
+        match Session::open(self.display.as_deref(), config.clone()) {
+            Ok(mut session) => {
+                log_info!("Reconnected to X11 display {}", session.name());
+                self.backoff = None;
+                emit_if_wanted(WindowEvent::Reconnected(session.name()));
+                session.start(&mut emit_if_wanted);
+                self.session = Some(session);
+            }
+            Err(e) => {
+                log_warn!("Reconnect failed: {}", e);
+                if !retry.failed() {
+                    log_error!("Giving up reconnecting after {} attempts", retry.attempts);
+                    return Err(WinshiftError::ConnectionLost);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Schedules reconnection attempts with exponential backoff.
+struct Backoff {
+    policy: ReconnectPolicy,
+    delay: Duration,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+impl Backoff {
+    /// `None` if the policy allows no attempt at all.
+    fn new(policy: ReconnectPolicy) -> Option<Self> {
+        if policy.max_attempts == Some(0) {
+            return None;
+        }
+        Some(Self {
+            policy,
+            delay: policy.initial_delay,
+            attempts: 0,
+            next_attempt: Instant::now() + policy.initial_delay,
+        })
+    }
+
+    /// Records a failed attempt and schedules the next one. Returns `false`
+    /// once the policy allows no more attempts.
+    fn failed(&mut self) -> bool {
+        self.attempts += 1;
+        if self
+            .policy
+            .max_attempts
+            .is_some_and(|max| self.attempts >= max)
+        {
+            return false;
+        }
+        self.delay = (self.delay * 2).min(self.policy.max_delay);
+        self.next_attempt = Instant::now() + self.delay;
+        true
+    }
+}
+
+pub(crate) fn current_focus() -> Result<Option<WindowInfo>, WinshiftError> {
+    log_debug!("Querying current focus");
+    let session = Session::open(None, HookConfig::default())?;
+    Ok(session.active_window_info())
+}
+
+#[cfg(feature = "xrandr")]
+pub(crate) fn current_monitor() -> Result<Option<Monitor>, WinshiftError> {
+    log_debug!("Querying current monitor");
+    let session = Session::open(None, HookConfig::default())?;
+    Ok(session.active_monitor())
+}
+
+pub(crate) fn list_windows() -> Result<Vec<WindowInfo>, WinshiftError> {
+    log_debug!("Listing client windows");
+    let session = Session::open(None, HookConfig::default())?;
+    Ok(session.client_windows())
+}
+
+pub(crate) fn stop_hook(hook_state: &HookState) -> Result<(), WinshiftError> {
+    log_debug!("Attempting to stop Linux hook");
+    let interrupt = hook_state
+        .interrupt
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let Some(fd) = interrupt.as_ref() else {
+        log_error!("Hook is not running");
+        return Err(WinshiftError::NotRunning);
+    };
+
+    // Send interrupt signal through the pipe
+    let buf = [0u8; 1];
+    if unsafe { write(fd.as_raw_fd(), buf.as_ptr() as *const c_void, 1) } != 1 {
+        let e = io::Error::last_os_error();
+        log_error!("Failed to send interrupt signal: {}", e);
+        return Err(WinshiftError::Pipe(e));
+    }
+    log_debug!("Linux hook stop signal sent");
+    Ok(())
+}
+
+fn create_pipe() -> Result<(OwnedFd, OwnedFd), WinshiftError> {
+    let mut fds: [RawFd; 2] = [-1, -1];
+    if unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } != 0 {
+        let e = io::Error::last_os_error();
+        log_error!("Failed to create interrupt pipe: {}", e);
+        return Err(WinshiftError::Pipe(e));
+    }
+    // SAFETY: pipe2() succeeded, so both descriptors are open and owned by us
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+#[derive(Default)]
+struct FocusState {
+    // Current value of _NET_ACTIVE_WINDOW
+    active_window: xlib::Window,
+    // Last window reported to the handler and the one focused before it
+    window: xlib::Window,
+    previous_window: xlib::Window,
+    title: String,
+}
+
+impl FocusState {
+    /// Whether focusing `info` differs from what was last reported, as far as
+    /// `dedup` is concerned.
+    fn is_change(&self, info: &WindowInfo, dedup: DedupStrategy) -> bool {
+        match dedup {
+            DedupStrategy::Title => info.title != self.title,
+            DedupStrategy::Window => info.id != self.window,
+            DedupStrategy::WindowAndTitle => info.id != self.window || info.title != self.title,
+            DedupStrategy::None => true,
+        }
+    }
+}
+
+/// One screen of a display; with several screens each has its own root
+/// window and, under EWMH, its own active window.
+struct Screen {
+    number: u32,
+    root: xlib::Window,
+    state: FocusState,
+    pending_focus: Option<PendingFocus>,
+    // Cached root window properties describing the virtual desktops
+    current_desktop: Option<u32>,
+    desktop_count: Option<u32>,
+    desktop_names: Vec<String>,
+    // Last known state of every managed window, while state changes are reported
+    clients: HashMap<xlib::Window, WindowState>,
+    // XRandR monitors, refreshed when the screen configuration changes
+    monitors: Vec<Monitor>,
+    // Override-redirect windows created since the hook started, whose
+    // destruction is not reported either
+    unmanaged: HashSet<xlib::Window>,
+}
+
+impl Screen {
+    fn desktop(&self, index: u32) -> Desktop {
+        Desktop {
+            index,
+            name: self.desktop_names.get(index as usize).cloned(),
+        }
+    }
+
+    fn current_desktop(&self) -> Option<Desktop> {
+        self.current_desktop.map(|index| self.desktop(index))
+    }
+
+    unsafe fn read_desktops(&mut self, display: *mut xlib::Display, atoms: &Atoms) {
+        self.current_desktop = get_cardinal(display, self.root, atoms.net_current_desktop);
+        self.desktop_count = get_cardinal(display, self.root, atoms.net_number_of_desktops);
+        self.desktop_names =
+            get_utf8_list(display, self.root, atoms.net_desktop_names, atoms).unwrap_or_default();
+        log_trace!(
+            "Desktops on screen {}: current {:?}, count {:?}, names {:?}",
+            self.number,
+            self.current_desktop,
+            self.desktop_count,
+            self.desktop_names
         );
-        log_trace!("Input selection set on root window");
-
-        let active_window_atom =
-            xlib::XInternAtom(display, "_NET_ACTIVE_WINDOW\0".as_ptr() as *const i8, 0);
-        let wm_name_atom = xlib::XInternAtom(display, "WM_NAME\0".as_ptr() as *const i8, 0);
-        let net_wm_name_atom =
-            xlib::XInternAtom(display, "_NET_WM_NAME\0".as_ptr() as *const i8, 0);
-        log_trace!("X11 atoms initialized");
-
-        let mut active_window: xlib::Window = 0;
-        let mut last_title = String::new();
-
-        // Set up error handler
-        let old_handler = xlib::XSetErrorHandler(Some(x_error_handler));
-        log_trace!("X11 error handler set");
-
-        let x11_fd = xlib::XConnectionNumber(display) as RawFd;
-        log_debug!("X11 connection file descriptor: {}", x11_fd);
-
-        let mut in_fds: fd_set = std::mem::zeroed();
-        FD_ZERO(&mut in_fds);
-        FD_SET(x11_fd, &mut in_fds);
-        FD_SET(INTERRUPT_PIPE[0], &mut in_fds);
-
-        let max_fd = x11_fd.max(INTERRUPT_PIPE[0]) + 1;
-
-        loop {
-            log_trace!("Waiting for X11 events or interrupt signal");
-            let mut read_fds = in_fds;
-
-            if select(
-                max_fd,
-                &mut read_fds,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-            ) > 0
+    }
+
+    /// Starts watching windows newly added to the client list and forgets
+    /// the ones that went away; windows already tracked are not queried again.
+    unsafe fn track_clients(&mut self, display: *mut xlib::Display, atoms: &Atoms) {
+        let clients: HashSet<xlib::Window> = get_client_list(display, self.root, atoms)
+            .into_iter()
+            .collect();
+        let active_window = self.state.active_window;
+        self.clients
+            .retain(|window, _| *window == active_window || clients.contains(window));
+        for window in clients {
+            if let Entry::Vacant(entry) = self.clients.entry(window) {
+                xlib::XSelectInput(display, window, xlib::PropertyChangeMask);
+                entry.insert(get_window_state(display, window, atoms));
+            }
+        }
+        log_trace!(
+            "Tracking state of {} window(s) on screen {}",
+            self.clients.len(),
+            self.number
+        );
+    }
+}
+
+/// An open X11 connection watching the root windows for focus changes.
+pub(crate) struct Session {
+    display: *mut xlib::Display,
+    // Display name events are tagged with
+    name: Arc<str>,
+    screens: Vec<Screen>,
+    atoms: Atoms,
+    config: HookConfig,
+    #[cfg(feature = "idle")]
+    idle: Option<IdleTracker>,
+    // Base of the XRandR event codes, if monitors are tracked
+    #[cfg(feature = "xrandr")]
+    xrandr_event_base: Option<c_int>,
+    // Recoverable errors waiting to be reported
+    errors: Vec<WinshiftError>,
+    // Set by x_io_error_exit once the connection is gone; boxed so Xlib can
+    // keep a pointer to it
+    lost: Box<AtomicBool>,
+}
+
+type ErrorHandler = unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int;
+type IoErrorHandler = unsafe extern "C" fn(*mut xlib::Display) -> c_int;
+type IoErrorExitHandler = unsafe extern "C" fn(*mut xlib::Display, *mut c_void);
+type SetIoErrorExitHandler =
+    unsafe extern "C" fn(*mut xlib::Display, Option<IoErrorExitHandler>, *mut c_void);
+
+// The Xlib error handlers are process-wide, so they are installed when the
+// first session opens and the previous ones restored when the last session closes.
+// Errors on displays other than the sessions' go to the previous handlers.
+struct ErrorHandlers {
+    // Displays of the open sessions
+    displays: Vec<usize>,
+    error: Option<ErrorHandler>,
+    io_error: Option<IoErrorHandler>,
+}
+
+static ERROR_HANDLERS: Mutex<ErrorHandlers> = Mutex::new(ErrorHandlers {
+    displays: Vec::new(),
+    error: None,
+    io_error: None,
+});
+
+// XSetIOErrorExitHandler only exists since libX11 1.7, so it is looked up at runtime
+static SET_IO_ERROR_EXIT_HANDLER: OnceLock<Option<SetIoErrorExitHandler>> = OnceLock::new();
+
+static INIT_THREADS: Once = Once::new();
+
+thread_local! {
+    // Errors caught by x_error_handler, tagged with their display. Xlib calls
+    // the handler on the thread that made the failing request.
+    static X_ERRORS: RefCell<Vec<(usize, WinshiftError)>> = const { RefCell::new(Vec::new()) };
+}
+
+// SAFETY: the display connection is owned by the session and only ever used by
+// the thread currently holding it.
+unsafe impl Send for Session {}
+
+impl Session {
+    pub(crate) fn open(name: Option<&str>, config: HookConfig) -> Result<Self, WinshiftError> {
+        // Hooks on different threads each use their own connection
+        INIT_THREADS.call_once(|| unsafe {
+            xlib::XInitThreads();
+        });
+
+        unsafe {
+            let display_name = match name {
+                Some(name) => Some(CString::new(name).map_err(|_| {
+                    log_error!("Invalid X11 display name: {:?}", name);
+                    WinshiftError::DisplayOpen {
+                        display: Some(name.to_string()),
+                    }
+                })?),
+                None => None,
+            };
+            let display = xlib::XOpenDisplay(
+                display_name
+                    .as_ref()
+                    .map_or(std::ptr::null(), |name| name.as_ptr()),
+            );
+            if display.is_null() {
+                log_error!("Failed to open X11 display");
+                return Err(WinshiftError::DisplayOpen {
+                    display: name.map(str::to_string),
+                });
+            }
+            let name: Arc<str> = CStr::from_ptr(xlib::XDisplayString(display))
+                .to_string_lossy()
+                .into();
+            log_debug!("X11 display {} opened successfully", name);
+
+            // Set up error handlers
             {
-                if libc::FD_ISSET(INTERRUPT_PIPE[0], &read_fds) {
-                    log_debug!("Received interrupt signal");
-                    let mut buf = [0u8; 1];
-                    read(INTERRUPT_PIPE[0], buf.as_mut_ptr() as *mut c_void, 1);
-                    break;
+                let mut handlers = ERROR_HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+                if handlers.displays.is_empty() {
+                    handlers.error = xlib::XSetErrorHandler(Some(x_error_handler));
+                    handlers.io_error = xlib::XSetIOErrorHandler(Some(x_io_error_handler));
+                    log_trace!("X11 error handlers set");
                 }
+                handlers.displays.push(display as usize);
+            }
 
-                if libc::FD_ISSET(x11_fd, &read_fds) {
-                    while xlib::XPending(display) > 0 {
-                        let mut event: xlib::XEvent = std::mem::zeroed();
-                        xlib::XNextEvent(display, &mut event);
-                        log_trace!("Received X11 event type: {}", event.get_type());
-
-                        match event.get_type() {
-                            xlib::PropertyNotify => {
-                                let xproperty = event.property;
-                                if xproperty.atom == active_window_atom {
-                                    log_debug!("Active window property changed");
-                                    let new_active_window =
-                                        get_active_window(display, root, active_window_atom);
-                                    if new_active_window != active_window {
-                                        log_debug!("New active window: {}", new_active_window);
-                                        active_window = new_active_window;
-                                        if let Some(window_title) = get_window_title(
-                                            display,
-                                            active_window,
-                                            wm_name_atom,
-                                            net_wm_name_atom,
-                                        ) {
-                                            if window_title != last_title {
-                                                log_info!(
-                                                    "Window focus changed: '{}' -> '{}'",
-                                                    last_title,
-                                                    window_title
-                                                );
-                                                last_title = window_title.clone();
-                                                if let Ok(guard) = handler.read() {
-                                                    guard.on_focus_change(window_title);
-                                                }
-                                            }
-                                        }
-                                    }
-                                } else if (xproperty.atom == wm_name_atom
-                                    || xproperty.atom == net_wm_name_atom)
-                                    && xproperty.window == active_window
-                                {
-                                    log_debug!("Window title property changed");
-                                    if let Some(window_title) = get_window_title(
-                                        display,
-                                        active_window,
-                                        wm_name_atom,
-                                        net_wm_name_atom,
-                                    ) {
-                                        if window_title != last_title {
-                                            log_info!(
-                                                "Window title changed: '{}' -> '{}'",
-                                                last_title,
-                                                window_title
-                                            );
-                                            last_title = window_title.clone();
-                                            if let Ok(guard) = handler.read() {
-                                                guard.on_focus_change(window_title);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            xlib::CreateNotify | xlib::DestroyNotify => {
-                                log_debug!("Window created or destroyed");
-                                active_window =
-                                    get_active_window(display, root, active_window_atom);
-                                if let Some(window_title) = get_window_title(
-                                    display,
-                                    active_window,
-                                    wm_name_atom,
-                                    net_wm_name_atom,
-                                ) {
-                                    if window_title != last_title {
-                                        log_info!(
-                                            "Window changed: '{}' -> '{}'",
-                                            last_title,
-                                            window_title
-                                        );
-                                        last_title = window_title.clone();
-                                        if let Ok(guard) = handler.read() {
-                                            guard.on_focus_change(window_title);
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                log_trace!("Ignoring event type: {}", event.get_type());
-                            }
-                        }
+            let mut screens: Vec<Screen> = (0..xlib::XScreenCount(display))
+                .map(|number| {
+                    let root = xlib::XRootWindow(display, number);
+                    xlib::XSelectInput(
+                        display,
+                        root,
+                        xlib::PropertyChangeMask | xlib::SubstructureNotifyMask,
+                    );
+                    Screen {
+                        number: number as u32,
+                        root,
+                        state: FocusState::default(),
+                        pending_focus: None,
+                        current_desktop: None,
+                        desktop_count: None,
+                        desktop_names: Vec::new(),
+                        clients: HashMap::new(),
+                        monitors: Vec::new(),
+                        unmanaged: HashSet::new(),
                     }
+                })
+                .collect();
+            log_trace!("Input selection set on {} root window(s)", screens.len());
+
+            let atoms = Atoms::intern(display);
+            log_trace!("X11 atoms initialized");
+            for screen in &mut screens {
+                screen.read_desktops(display, &atoms);
+            }
+
+            #[cfg(feature = "xrandr")]
+            let xrandr_event_base = init_xrandr(display, &mut screens);
+
+            #[cfg(feature = "idle")]
+            let idle = config
+                .idle_threshold
+                .and_then(|threshold| IdleTracker::new(display, &screens, threshold));
+
+            // Keep Xlib from exiting the process when the connection drops
+            let lost = Box::new(AtomicBool::new(false));
+            match set_io_error_exit_handler() {
+                Some(set_exit_handler) => set_exit_handler(
+                    display,
+                    Some(x_io_error_exit),
+                    &*lost as *const AtomicBool as *mut c_void,
+                ),
+                None => {
+                    log_warn!("XSetIOErrorExitHandler is unavailable, losing the X11 connection will exit the process");
                 }
-            } else if *libc::__errno_location() != EINTR {
-                log_warn!("select() failed");
             }
+
+            Ok(Self {
+                display,
+                name,
+                screens,
+                atoms,
+                config,
+                #[cfg(feature = "idle")]
+                idle,
+                #[cfg(feature = "xrandr")]
+                xrandr_event_base,
+                errors: Vec::new(),
+                lost,
+            })
         }
+    }
 
-        // Reset error handler
-        log_trace!("Resetting X11 error handler");
-        xlib::XSetErrorHandler(old_handler);
-        xlib::XCloseDisplay(display);
-        log_debug!("X11 display closed");
+    /// Reads the window _NET_ACTIVE_WINDOW currently points at, looking at
+    /// the default screen first.
+    pub(crate) fn active_window_info(&self) -> Option<WindowInfo> {
+        let (_, window) = self.active_window()?;
+        unsafe { get_window_info(self.display, window, &self.atoms) }
+    }
 
-        // Close the self-pipe
-        close(INTERRUPT_PIPE[0]);
-        close(INTERRUPT_PIPE[1]);
-        log_trace!("Interrupt pipe closed");
+    /// The monitor showing most of the active window.
+    #[cfg(feature = "xrandr")]
+    pub(crate) fn active_monitor(&self) -> Option<Monitor> {
+        let (root, window) = self.active_window()?;
+        let screen = &self.screens[self.screen_of_root(root)?];
+        unsafe { get_window_monitor(self.display, screen, window) }
     }
 
-    log_debug!("Linux hook stopped");
-    Ok(())
+    /// The active window and its root, looking at the default screen first.
+    fn active_window(&self) -> Option<(xlib::Window, xlib::Window)> {
+        unsafe {
+            let default_root = xlib::XDefaultRootWindow(self.display);
+            let mut roots: Vec<xlib::Window> = self.screens.iter().map(|s| s.root).collect();
+            roots.sort_by_key(|&root| root != default_root);
+            roots.into_iter().find_map(|root| {
+                let window = get_active_window(self.display, root, self.atoms.active_window)?;
+                (window != 0).then_some((root, window))
+            })
+        }
+    }
+
+    /// Reads every window managed by the window manager, bottom to top, one
+    /// screen after the other.
+    pub(crate) fn client_windows(&self) -> Vec<WindowInfo> {
+        unsafe {
+            self.screens
+                .iter()
+                .flat_map(|screen| get_client_list(self.display, screen.root, &self.atoms))
+                .filter_map(|window| get_window_info(self.display, window, &self.atoms))
+                .collect()
+        }
+    }
+
+    /// Reads the initially focused window and emits it if configured to.
+    /// Also starts tracking the state of managed windows if state changes
+    /// are reported.
+    pub(crate) fn start(&mut self, emit: &mut dyn FnMut(WindowEvent)) {
+        if self.tracks_state() {
+            for screen in &mut self.screens {
+                unsafe { screen.track_clients(self.display, &self.atoms) };
+            }
+        }
+        if self.config.emit_initial {
+            log_debug!("Emitting initially focused window");
+            let mut emit = filter_events(self.config.events, &mut *emit);
+            for index in 0..self.screens.len() {
+                unsafe {
+                    // Nothing has been reported yet, so there is nothing to dedup against
+                    if let Some(mut event) = self.update_active_window(index, DedupStrategy::None) {
+                        // The initial window is reported right away, without settling
+                        event.initial = true;
+                        self.commit_focus(index, event, &mut emit);
+                    }
+                }
+            }
+            self.report_errors(&mut emit);
+        }
+        // Events read while waiting for replies above are already queued in
+        // Xlib, where waiting on the socket would not see them
+        self.process_pending(emit);
+    }
+
+    /// Handles every event Xlib has queued or can read without blocking.
+    pub(crate) fn process_pending(&mut self, emit: &mut dyn FnMut(WindowEvent)) {
+        let mut emit = filter_events(self.config.events, emit);
+        unsafe {
+            while !self.is_lost() && xlib::XPending(self.display) > 0 {
+                let mut event: xlib::XEvent = std::mem::zeroed();
+                xlib::XNextEvent(self.display, &mut event);
+                log_trace!("Received X11 event type: {}", event.get_type());
+
+                self.handle_event(&event, &mut emit);
+                self.report_errors(&mut emit);
+            }
+        }
+    }
+
+    pub(crate) fn name(&self) -> Arc<str> {
+        self.name.clone()
+    }
+
+    /// Whether the connection to the X server has been lost. A lost session
+    /// must not make any further requests.
+    pub(crate) fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Acquire)
+    }
+
+    /// When a focus change is waiting to settle, the time it will be reported.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let settle = self
+            .screens
+            .iter()
+            .filter_map(|screen| screen.pending_focus.as_ref())
+            .map(|pending| pending.deadline)
+            .min();
+        #[cfg(feature = "idle")]
+        let settle = settle
+            .into_iter()
+            .chain(self.idle.as_ref().map(|idle| idle.next_check))
+            .min();
+        settle
+    }
+
+    /// Reports focus changes that have been held for the settle delay and,
+    /// with idle tracking, changes between idle and active.
+    pub(crate) fn flush_due(&mut self, now: Instant, emit: &mut dyn FnMut(WindowEvent)) {
+        let mut emit = filter_events(self.config.events, emit);
+        for index in 0..self.screens.len() {
+            let screen = &mut self.screens[index];
+            if screen
+                .pending_focus
+                .as_ref()
+                .is_some_and(|pending| pending.deadline <= now)
+            {
+                if let Some(pending) = screen.pending_focus.take() {
+                    log_debug!("Focus settled on window {}", pending.event.window.id);
+                    self.commit_focus(index, pending.event, &mut emit);
+                }
+            }
+        }
+
+        #[cfg(feature = "idle")]
+        if let Some(idle) = &mut self.idle {
+            let root = self.screens[0].root;
+            if let Some(event) = unsafe { idle.check(self.display, root, &self.name, now) } {
+                emit(event);
+            }
+            // Events read while waiting for the reply must not wait for the socket
+            self.process_pending(&mut emit);
+        }
+    }
+
+    fn tracks_state(&self) -> bool {
+        self.config.events.contains(EventKinds::STATE)
+    }
+
+    fn screen_of_root(&self, root: xlib::Window) -> Option<usize> {
+        self.screens.iter().position(|screen| screen.root == root)
+    }
+
+    /// Emits the errors recorded since the last call, X protocol errors first.
+    fn report_errors(&mut self, emit: &mut dyn FnMut(WindowEvent)) {
+        let display = self.display as usize;
+        let x_errors: Vec<_> = X_ERRORS.with_borrow_mut(|errors| {
+            let (ours, others) = errors
+                .drain(..)
+                .partition(|(error_display, _)| *error_display == display);
+            *errors = others;
+            ours
+        });
+        // Handlers may open connections of their own, so nothing stays borrowed here
+        for (_, error) in x_errors {
+            emit(WindowEvent::Error(Arc::new(error)));
+        }
+        for error in self.errors.drain(..) {
+            emit(WindowEvent::Error(Arc::new(error)));
+        }
+    }
+
+    fn property_read_failed(&mut self, window: xlib::Window, atom: &str) {
+        self.errors.push(WinshiftError::PropertyRead {
+            window,
+            atom: atom.to_string(),
+        });
+    }
+
+    unsafe fn handle_event(&mut self, event: &xlib::XEvent, emit: &mut dyn FnMut(WindowEvent)) {
+        #[cfg(feature = "idle")]
+        if let Some(idle) = &mut self.idle {
+            if event.get_type() == idle.event_base + xss::ScreenSaverNotify {
+                // The screen saver turning on or off is a good time to look again
+                log_debug!("Screen saver state changed");
+                idle.next_check = Instant::now();
+                return;
+            }
+        }
+
+        #[cfg(feature = "xrandr")]
+        if let Some(event_base) = self.xrandr_event_base {
+            if event.get_type() == event_base + xrandr::RRScreenChangeNotify {
+                let mut event = *event;
+                xrandr::XRRUpdateConfiguration(&mut event);
+                let changed =
+                    &*(&event as *const xlib::XEvent as *const xrandr::XRRScreenChangeNotifyEvent);
+                if let Some(index) = self.screen_of_root(changed.root) {
+                    log_debug!("Screen {} configuration changed", index);
+                    let screen = &mut self.screens[index];
+                    screen.monitors = get_monitors(self.display, screen.root);
+                }
+                return;
+            }
+        }
+
+        match event.get_type() {
+            xlib::PropertyNotify => {
+                let xproperty = event.property;
+                if xproperty.atom == self.atoms.net_current_desktop
+                    || xproperty.atom == self.atoms.net_number_of_desktops
+                    || xproperty.atom == self.atoms.net_desktop_names
+                {
+                    let Some(index) = self.screen_of_root(xproperty.window) else {
+                        return;
+                    };
+                    self.handle_desktop_change(index, emit);
+                } else if xproperty.atom == self.atoms.net_client_list {
+                    let Some(index) = self.screen_of_root(xproperty.window) else {
+                        return;
+                    };
+                    if self.tracks_state() {
+                        self.screens[index].track_clients(self.display, &self.atoms);
+                    }
+                } else if xproperty.atom == self.atoms.net_wm_state
+                    || xproperty.atom == self.atoms.wm_state
+                    || xproperty.atom == xlib::XA_WM_HINTS
+                {
+                    self.handle_state_change(xproperty.window, emit);
+                } else if xproperty.atom == self.atoms.active_window {
+                    let Some(index) = self.screen_of_root(xproperty.window) else {
+                        return;
+                    };
+                    log_debug!("Active window property changed on screen {}", index);
+                    self.handle_active_window_change(index, self.config.dedup, emit);
+                } else if xproperty.atom == self.atoms.wm_name
+                    || xproperty.atom == self.atoms.net_wm_name
+                {
+                    let Some(index) = self
+                        .screens
+                        .iter()
+                        .position(|screen| screen.state.active_window == xproperty.window)
+                    else {
+                        return;
+                    };
+                    log_debug!("Window title property changed");
+                    self.handle_title_change(index, emit);
+                }
+            }
+            xlib::CreateNotify => {
+                let created = event.create_window;
+                let Some(index) = self.screen_of_root(created.parent) else {
+                    return;
+                };
+                if created.override_redirect != 0 {
+                    // Menus, tooltips and the like, not application windows
+                    log_trace!("Ignoring override-redirect window {}", created.window);
+                    self.screens[index].unmanaged.insert(created.window);
+                    return;
+                }
+                log_debug!("Window created: {}", created.window);
+                emit(WindowEvent::WindowCreated(LifecycleEvent {
+                    window: created.window,
+                    display: self.name(),
+                    screen: self.screens[index].number,
+                }));
+            }
+            xlib::DestroyNotify => {
+                let destroyed = event.destroy_window;
+                let Some(index) = self.screen_of_root(destroyed.event) else {
+                    return;
+                };
+                if self.screens[index].unmanaged.remove(&destroyed.window) {
+                    return;
+                }
+                log_debug!("Window destroyed: {}", destroyed.window);
+                self.screens[index].clients.remove(&destroyed.window);
+                emit(WindowEvent::WindowDestroyed(LifecycleEvent {
+                    window: destroyed.window,
+                    display: self.name(),
+                    screen: self.screens[index].number,
+                }));
+                // Not every window manager updates _NET_ACTIVE_WINDOW when the focused window goes away.
+                // Only a real update of the property is reported without a change of window.
+                let dedup = match self.config.dedup {
+                    DedupStrategy::None => DedupStrategy::Window,
+                    dedup => dedup,
+                };
+                self.handle_active_window_change(index, dedup, emit);
+            }
+            _ => {
+                log_trace!("Ignoring event type: {}", event.get_type());
+            }
+        }
+    }
+
+    unsafe fn handle_active_window_change(
+        &mut self,
+        index: usize,
+        dedup: DedupStrategy,
+        emit: &mut dyn FnMut(WindowEvent),
+    ) {
+        let Some(event) = self.update_active_window(index, dedup) else {
+            return;
+        };
+        match self.config.settle_delay {
+            Some(delay) => {
+                let screen = &mut self.screens[index];
+                if let Some(skipped) = &screen.pending_focus {
+                    log_trace!(
+                        "Dropping unsettled focus on window {}",
+                        skipped.event.window.id
+                    );
+                }
+                screen.pending_focus = Some(PendingFocus {
+                    deadline: event.timestamp + delay,
+                    event,
+                });
+            }
+            None => self.commit_focus(index, event, emit),
+        }
+    }
+
+    unsafe fn handle_desktop_change(&mut self, index: usize, emit: &mut dyn FnMut(WindowEvent)) {
+        let screen = &mut self.screens[index];
+        let previous = screen.current_desktop();
+        screen.read_desktops(self.display, &self.atoms);
+        let Some(desktop) = screen.current_desktop() else {
+            return;
+        };
+        if previous.as_ref().map(|previous| previous.index) == Some(desktop.index) {
+            return;
+        }
+
+        log_info!(
+            "Desktop changed: {:?} -> {} ({:?})",
+            previous.as_ref().map(|previous| previous.index),
+            desktop.index,
+            desktop.name
+        );
+        emit(WindowEvent::DesktopChanged(DesktopEvent {
+            desktop,
+            previous,
+            count: screen.desktop_count,
+            display: self.name.clone(),
+            screen: screen.number,
+        }));
+    }
+
+    unsafe fn handle_state_change(
+        &mut self,
+        window: xlib::Window,
+        emit: &mut dyn FnMut(WindowEvent),
+    ) {
+        if !self.tracks_state() {
+            return;
+        }
+        let Some(screen) = self
+            .screens
+            .iter_mut()
+            .find(|screen| screen.clients.contains_key(&window))
+        else {
+            return;
+        };
+        let Some(info) = get_window_info(self.display, window, &self.atoms) else {
+            return;
+        };
+        let state = info.state;
+        let Some(previous) = screen.clients.insert(window, state) else {
+            return;
+        };
+        if previous == state {
+            return;
+        }
+        if !self.config.filter.allows(&info) {
+            log_trace!("Ignoring state change of excluded window {}", window);
+            return;
+        }
+
+        log_debug!("Window {} state changed: {:?}", window, state);
+        emit(WindowEvent::StateChanged(StateEvent {
+            window,
+            previous,
+            state,
+            display: self.name.clone(),
+            screen: screen.number,
+        }));
+    }
+
+    unsafe fn handle_title_change(&mut self, index: usize, emit: &mut dyn FnMut(WindowEvent)) {
+        let window = self.screens[index].state.active_window;
+        let Some(info) = get_window_info(self.display, window, &self.atoms) else {
+            self.property_read_failed(window, "_NET_WM_NAME");
+            return;
+        };
+        if !self.config.filter.allows(&info) {
+            log_trace!("Ignoring title change of excluded window {}", info.id);
+            return;
+        }
+
+        let screen = &mut self.screens[index];
+        // A window that has not settled yet only has its pending event updated
+        if let Some(pending) = &mut screen.pending_focus {
+            if pending.event.window.id == info.id {
+                pending.event.window = info;
+                return;
+            }
+        }
+
+        if info.id != screen.state.window {
+            // Focus on this window was never reported, e.g. because dedup
+            // suppressed it, so a new title makes it a focus change
+            if screen.state.is_change(&info, self.config.dedup) {
+                let mut event = FocusEvent::new(info, None, self.name.clone(), screen.number);
+                event.monitor = get_window_monitor(self.display, screen, window);
+                event.desktop = screen.current_desktop();
+                self.commit_focus(index, event, emit);
+            }
+            return;
+        }
+
+        let state = &mut screen.state;
+        if info.title != state.title {
+            log_info!(
+                "Window title changed: '{}' -> '{}'",
+                state.title,
+                info.title
+            );
+            state.title = info.title.clone();
+            let mut event = FocusEvent::new(
+                info,
+                non_zero_window(state.previous_window),
+                self.name.clone(),
+                screen.number,
+            );
+            event.monitor = get_window_monitor(self.display, screen, window);
+            event.desktop = screen.current_desktop();
+            emit(WindowEvent::TitleChanged(event));
+        }
+    }
+
+    /// Re-reads _NET_ACTIVE_WINDOW and returns the focus change it amounts to
+    /// under `dedup`, if any.
+    unsafe fn update_active_window(
+        &mut self,
+        index: usize,
+        dedup: DedupStrategy,
+    ) -> Option<FocusEvent> {
+        let display = self.display;
+        let root = self.screens[index].root;
+        let Some(new_active_window) = get_active_window(display, root, self.atoms.active_window)
+        else {
+            self.property_read_failed(root, "_NET_ACTIVE_WINDOW");
+            return None;
+        };
+        let screen = &mut self.screens[index];
+        if new_active_window == screen.state.active_window && dedup != DedupStrategy::None {
+            return None;
+        }
+        log_debug!("New active window: {}", new_active_window);
+        screen.state.active_window = new_active_window;
+        if new_active_window == 0 {
+            screen.pending_focus = None;
+            return None;
+        }
+
+        // Title changes are reported on the window itself, not on the root
+        xlib::XSelectInput(display, new_active_window, xlib::PropertyChangeMask);
+
+        let Some(info) = get_window_info(display, new_active_window, &self.atoms) else {
+            self.property_read_failed(new_active_window, "_NET_WM_NAME");
+            return None;
+        };
+        let screen = &mut self.screens[index];
+        if !self.config.filter.allows(&info) {
+            // Excluded windows leave the reported state untouched
+            log_debug!("Window {} excluded by filter", new_active_window);
+            screen.pending_focus = None;
+            return None;
+        }
+        if !screen.state.is_change(&info, dedup) {
+            // Focus went back to the reported window before the pending one settled
+            screen.pending_focus = None;
+            return None;
+        }
+        let mut event = FocusEvent::new(info, None, self.name.clone(), screen.number);
+        event.monitor = get_window_monitor(display, screen, new_active_window);
+        event.desktop = screen.current_desktop();
+        if self.config.events.contains(EventKinds::STATE) {
+            // The focused window is tracked even if the window manager does not list it
+            screen
+                .clients
+                .entry(new_active_window)
+                .or_insert(event.window.state);
+        }
+        Some(event)
+    }
+
+    fn commit_focus(
+        &mut self,
+        index: usize,
+        mut event: FocusEvent,
+        emit: &mut dyn FnMut(WindowEvent),
+    ) {
+        let state = &mut self.screens[index].state;
+        log_info!(
+            "Window focus changed: '{}' -> '{}'",
+            state.title,
+            event.window.title
+        );
+        state.previous_window = state.window;
+        state.window = event.window.id;
+        state.title = event.window.title.clone();
+        event.previous_window = non_zero_window(state.previous_window);
+        emit(WindowEvent::FocusChanged(event));
+    }
 }
 
-pub fn stop_hook() -> Result<(), WinshiftError> {
-    log_debug!("Attempting to stop Linux hook");
-    unsafe {
-        // Send interrupt signal through the pipe
-        let buf = [0u8; 1];
-        if write(INTERRUPT_PIPE[1], buf.as_ptr() as *const c_void, 1) != 1 {
-            log_error!("Failed to send interrupt signal");
-            return Err(WinshiftError::StopError);
+/// Polls the server's idle time through the MIT-SCREEN-SAVER extension.
+#[cfg(feature = "idle")]
+struct IdleTracker {
+    threshold: Duration,
+    event_base: c_int,
+    // When input stopped, while the user is idle
+    idle_since: Option<SystemTime>,
+    next_check: Instant,
+}
+
+// While idle, how often to look for input again
+#[cfg(feature = "idle")]
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "idle")]
+impl IdleTracker {
+    unsafe fn new(
+        display: *mut xlib::Display,
+        screens: &[Screen],
+        threshold: Duration,
+    ) -> Option<Self> {
+        let mut event_base = 0;
+        let mut error_base = 0;
+        if xss::XScreenSaverQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+            log_warn!("MIT-SCREEN-SAVER extension is not available, idle tracking disabled");
+            return None;
+        }
+        for screen in screens {
+            xss::XScreenSaverSelectInput(display, screen.root, xss::ScreenSaverNotifyMask);
         }
+        log_trace!("Idle tracking enabled with threshold {:?}", threshold);
+        Some(Self {
+            threshold,
+            event_base,
+            idle_since: None,
+            next_check: Instant::now(),
+        })
+    }
+
+    /// Queries the idle time if a check is due and returns the resulting
+    /// transition, if any.
+    unsafe fn check(
+        &mut self,
+        display: *mut xlib::Display,
+        root: xlib::Window,
+        name: &Arc<str>,
+        now: Instant,
+    ) -> Option<WindowEvent> {
+        if now < self.next_check {
+            return None;
+        }
+        let mut info: xss::XScreenSaverInfo = std::mem::zeroed();
+        if xss::XScreenSaverQueryInfo(display, root, &mut info) == 0 {
+            log_warn!("Failed to query idle time");
+            self.next_check = now + IDLE_POLL_INTERVAL;
+            return None;
+        }
+        let idle = Duration::from_millis(info.idle as u64);
+        log_trace!("Idle for {:?}", idle);
+
+        if idle < self.threshold {
+            self.next_check = now + (self.threshold - idle);
+            self.idle_since.take()?;
+            log_info!("User active again on {}", name);
+            return Some(WindowEvent::Active {
+                display: name.clone(),
+            });
+        }
+
+        self.next_check = now + IDLE_POLL_INTERVAL;
+        if self.idle_since.is_some() {
+            return None;
+        }
+        let since = SystemTime::now() - idle;
+        self.idle_since = Some(since);
+        log_info!("User idle on {} for {:?}", name, idle);
+        Some(WindowEvent::Idle {
+            display: name.clone(),
+            since,
+        })
+    }
+}
+
+struct PendingFocus {
+    event: FocusEvent,
+    deadline: Instant,
+}
+
+/// Wraps `emit` so that only the configured event kinds get through.
+fn filter_events(
+    events: EventKinds,
+    emit: &mut dyn FnMut(WindowEvent),
+) -> impl FnMut(WindowEvent) + '_ {
+    move |event| {
+        if events.matches(&event) {
+            emit(event)
+        }
+    }
+}
+
+impl AsRawFd for Session {
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe { xlib::XConnectionNumber(self.display) as RawFd }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let display = self.display as usize;
+        X_ERRORS.with_borrow_mut(|errors| {
+            errors.retain(|(error_display, _)| *error_display != display)
+        });
+        unsafe {
+            xlib::XCloseDisplay(self.display);
+
+            let mut handlers = ERROR_HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(index) = handlers.displays.iter().position(|&open| open == display) {
+                handlers.displays.swap_remove(index);
+            }
+            if handlers.displays.is_empty() {
+                // Reset error handlers
+                log_trace!("Resetting X11 error handlers");
+                xlib::XSetErrorHandler(handlers.error.take());
+                xlib::XSetIOErrorHandler(handlers.io_error.take());
+            }
+        }
+        log_debug!("X11 display closed");
     }
-    log_debug!("Linux hook stop signal sent");
-    Ok(())
 }
 
+struct Atoms {
+    active_window: xlib::Atom,
+    wm_name: xlib::Atom,
+    net_wm_name: xlib::Atom,
+    net_wm_pid: xlib::Atom,
+    utf8_string: xlib::Atom,
+    net_client_list: xlib::Atom,
+    net_client_list_stacking: xlib::Atom,
+    net_wm_desktop: xlib::Atom,
+    net_wm_state: xlib::Atom,
+    net_current_desktop: xlib::Atom,
+    wm_state: xlib::Atom,
+    net_number_of_desktops: xlib::Atom,
+    net_desktop_names: xlib::Atom,
+    state_modal: xlib::Atom,
+    state_sticky: xlib::Atom,
+    state_maximized_vert: xlib::Atom,
+    state_maximized_horz: xlib::Atom,
+    state_shaded: xlib::Atom,
+    state_skip_taskbar: xlib::Atom,
+    state_skip_pager: xlib::Atom,
+    state_hidden: xlib::Atom,
+    state_fullscreen: xlib::Atom,
+    state_above: xlib::Atom,
+    state_below: xlib::Atom,
+    state_demands_attention: xlib::Atom,
+}
+
+impl Atoms {
+    unsafe fn intern(display: *mut xlib::Display) -> Self {
+        Self {
+            active_window: xlib::XInternAtom(display, c"_NET_ACTIVE_WINDOW".as_ptr(), 0),
+            wm_name: xlib::XInternAtom(display, c"WM_NAME".as_ptr(), 0),
+            net_wm_name: xlib::XInternAtom(display, c"_NET_WM_NAME".as_ptr(), 0),
+            net_wm_pid: xlib::XInternAtom(display, c"_NET_WM_PID".as_ptr(), 0),
+            utf8_string: xlib::XInternAtom(display, c"UTF8_STRING".as_ptr(), 0),
+            net_client_list: xlib::XInternAtom(display, c"_NET_CLIENT_LIST".as_ptr(), 0),
+            net_client_list_stacking: xlib::XInternAtom(
+                display,
+                c"_NET_CLIENT_LIST_STACKING".as_ptr(),
+                0,
+            ),
+            net_wm_desktop: xlib::XInternAtom(display, c"_NET_WM_DESKTOP".as_ptr(), 0),
+            net_wm_state: xlib::XInternAtom(display, c"_NET_WM_STATE".as_ptr(), 0),
+            net_current_desktop: xlib::XInternAtom(display, c"_NET_CURRENT_DESKTOP".as_ptr(), 0),
+            wm_state: xlib::XInternAtom(display, c"WM_STATE".as_ptr(), 0),
+            net_number_of_desktops: xlib::XInternAtom(
+                display,
+                c"_NET_NUMBER_OF_DESKTOPS".as_ptr(),
+                0,
+            ),
+            net_desktop_names: xlib::XInternAtom(display, c"_NET_DESKTOP_NAMES".as_ptr(), 0),
+            state_modal: xlib::XInternAtom(display, c"_NET_WM_STATE_MODAL".as_ptr(), 0),
+            state_sticky: xlib::XInternAtom(display, c"_NET_WM_STATE_STICKY".as_ptr(), 0),
+            state_maximized_vert: xlib::XInternAtom(
+                display,
+                c"_NET_WM_STATE_MAXIMIZED_VERT".as_ptr(),
+                0,
+            ),
+            state_maximized_horz: xlib::XInternAtom(
+                display,
+                c"_NET_WM_STATE_MAXIMIZED_HORZ".as_ptr(),
+                0,
+            ),
+            state_shaded: xlib::XInternAtom(display, c"_NET_WM_STATE_SHADED".as_ptr(), 0),
+            state_skip_taskbar: xlib::XInternAtom(
+                display,
+                c"_NET_WM_STATE_SKIP_TASKBAR".as_ptr(),
+                0,
+            ),
+            state_skip_pager: xlib::XInternAtom(display, c"_NET_WM_STATE_SKIP_PAGER".as_ptr(), 0),
+            state_hidden: xlib::XInternAtom(display, c"_NET_WM_STATE_HIDDEN".as_ptr(), 0),
+            state_fullscreen: xlib::XInternAtom(display, c"_NET_WM_STATE_FULLSCREEN".as_ptr(), 0),
+            state_above: xlib::XInternAtom(display, c"_NET_WM_STATE_ABOVE".as_ptr(), 0),
+            state_below: xlib::XInternAtom(display, c"_NET_WM_STATE_BELOW".as_ptr(), 0),
+            state_demands_attention: xlib::XInternAtom(
+                display,
+                c"_NET_WM_STATE_DEMANDS_ATTENTION".as_ptr(),
+                0,
+            ),
+        }
+    }
+}
+
+fn non_zero_window(window: xlib::Window) -> Option<u64> {
+    (window != 0).then_some(window)
+}
+
+unsafe fn get_window_info(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    atoms: &Atoms,
+) -> Option<WindowInfo> {
+    let title = match get_window_title(display, window, atoms) {
+        Some(title) => title,
+        // Untitled windows are still reported, with an empty title
+        None if window_exists(display, window) => String::new(),
+        None => {
+            log_debug!("Window {} is gone", window);
+            return None;
+        }
+    };
+    let (class, instance) = match get_window_class(display, window) {
+        Some((class, instance)) => (Some(class), Some(instance)),
+        None => (None, None),
+    };
+    Some(WindowInfo {
+        id: window,
+        title,
+        class,
+        instance,
+        pid: get_window_pid(display, window, atoms.net_wm_pid),
+        machine: get_client_machine(display, window),
+        desktop: get_cardinal(display, window, atoms.net_wm_desktop),
+        state: get_window_state(display, window, atoms),
+    })
+}
+
+/// Whether `window` still exists; reading its properties fails silently once
+/// it has been destroyed.
+unsafe fn window_exists(display: *mut xlib::Display, window: xlib::Window) -> bool {
+    let mut attributes: xlib::XWindowAttributes = std::mem::zeroed();
+    xlib::XGetWindowAttributes(display, window, &mut attributes) != 0
+}
+
+/// Reads `_NET_ACTIVE_WINDOW` from `root`; `Some(0)` if no window is active
+/// or the window manager does not manage that screen, `None` if the read failed.
 unsafe fn get_active_window(
     display: *mut xlib::Display,
     root: xlib::Window,
     active_window_atom: xlib::Atom,
-) -> xlib::Window {
+) -> Option<xlib::Window> {
     log_trace!("Getting active window");
     let mut actual_type: xlib::Atom = 0;
     let mut actual_format: c_int = 0;
@@ -222,31 +1447,36 @@ unsafe fn get_active_window(
         active_window_atom,
         0,
         1,
-        xlib::False as i32,
+        xlib::False,
         xlib::XA_WINDOW,
         &mut actual_type,
         &mut actual_format,
         &mut nitems,
         &mut bytes_after,
         &mut prop as *mut *mut c_char as *mut *mut c_uchar,
-    ) == 0
-        && !prop.is_null()
+    ) != 0
     {
-        let window = *(prop as *const xlib::Window);
-        xlib::XFree(prop as *mut c_void);
-        log_trace!("Active window: {}", window);
-        window
-    } else {
         log_warn!("Failed to get active window");
-        0
+        return None;
+    }
+    if prop.is_null() {
+        log_trace!("No _NET_ACTIVE_WINDOW on root window {}", root);
+        return Some(0);
     }
+    let window = if actual_type == xlib::XA_WINDOW && nitems >= 1 {
+        *(prop as *const xlib::Window)
+    } else {
+        0
+    };
+    xlib::XFree(prop as *mut c_void);
+    log_trace!("Active window: {}", window);
+    Some(window)
 }
 
 unsafe fn get_window_title(
     display: *mut xlib::Display,
     window: xlib::Window,
-    wm_name_atom: xlib::Atom,
-    net_wm_name_atom: xlib::Atom,
+    atoms: &Atoms,
 ) -> Option<String> {
     log_trace!("Getting window title for window: {}", window);
     let mut actual_type: xlib::Atom = 0;
@@ -259,11 +1489,11 @@ unsafe fn get_window_title(
     if xlib::XGetWindowProperty(
         display,
         window,
-        net_wm_name_atom,
+        atoms.net_wm_name,
         0,
         1024,
-        xlib::False as i32,
-        xlib::XInternAtom(display, "UTF8_STRING\0".as_ptr() as *const i8, 0),
+        xlib::False,
+        atoms.utf8_string,
         &mut actual_type,
         &mut actual_format,
         &mut nitems,
@@ -282,10 +1512,10 @@ unsafe fn get_window_title(
     if xlib::XGetWindowProperty(
         display,
         window,
-        wm_name_atom,
+        atoms.wm_name,
         0,
         1024,
-        xlib::False as i32,
+        xlib::False,
         xlib::XA_STRING,
         &mut actual_type,
         &mut actual_format,
@@ -301,21 +1531,455 @@ unsafe fn get_window_title(
         return Some(title);
     }
 
-    log_warn!("Failed to get window title for window: {}", window);
-    None // Return None if unable to get window title
+    log_debug!("Window {} has no title", window);
+    None
+}
+
+unsafe fn get_client_machine(display: *mut xlib::Display, window: xlib::Window) -> Option<String> {
+    let mut property: xlib::XTextProperty = std::mem::zeroed();
+    if xlib::XGetWMClientMachine(display, window, &mut property) == 0 || property.value.is_null() {
+        return None;
+    }
+    let machine = CStr::from_ptr(property.value as *const c_char)
+        .to_string_lossy()
+        .into_owned();
+    xlib::XFree(property.value as *mut c_void);
+    Some(machine)
+}
+
+unsafe fn get_window_class(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+) -> Option<(String, String)> {
+    log_trace!("Getting window class for window: {}", window);
+    let mut hint: xlib::XClassHint = std::mem::zeroed();
+    if xlib::XGetClassHint(display, window, &mut hint) == 0 {
+        log_trace!("No WM_CLASS for window: {}", window);
+        return None;
+    }
+
+    let read = |ptr: *mut c_char| {
+        if ptr.is_null() {
+            String::new()
+        } else {
+            let value = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+            xlib::XFree(ptr as *mut c_void);
+            value
+        }
+    };
+    let instance = read(hint.res_name);
+    let class = read(hint.res_class);
+    log_trace!("Window class: {} (instance: {})", class, instance);
+    Some((class, instance))
+}
+
+unsafe fn get_window_pid(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    net_wm_pid_atom: xlib::Atom,
+) -> Option<u32> {
+    log_trace!("Getting pid for window: {}", window);
+    let pid = get_long_property(display, window, net_wm_pid_atom, xlib::XA_CARDINAL, 1)?
+        .first()
+        .map(|&pid| pid as u32);
+    log_trace!("Window pid: {:?}", pid);
+    pid
+}
+
+unsafe fn get_window_state(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    atoms: &Atoms,
+) -> WindowState {
+    let values = get_long_property(display, window, atoms.net_wm_state, xlib::XA_ATOM, 64)
+        .unwrap_or_default();
+    let iconic = get_long_property(display, window, atoms.wm_state, atoms.wm_state, 2)
+        .and_then(|values| values.first().copied())
+        == Some(ICONIC_STATE);
+    let hints = xlib::XGetWMHints(display, window);
+    let urgent = !hints.is_null() && (*hints).flags & xlib::XUrgencyHint != 0;
+    if !hints.is_null() {
+        xlib::XFree(hints as *mut c_void);
+    }
+
+    let has = |atom: xlib::Atom| values.contains(&atom);
+    WindowState {
+        modal: has(atoms.state_modal),
+        sticky: has(atoms.state_sticky),
+        maximized_vert: has(atoms.state_maximized_vert),
+        maximized_horz: has(atoms.state_maximized_horz),
+        shaded: has(atoms.state_shaded),
+        skip_taskbar: has(atoms.state_skip_taskbar),
+        skip_pager: has(atoms.state_skip_pager),
+        hidden: has(atoms.state_hidden),
+        fullscreen: has(atoms.state_fullscreen),
+        above: has(atoms.state_above),
+        below: has(atoms.state_below),
+        demands_attention: has(atoms.state_demands_attention),
+        iconic,
+        urgent,
+    }
+}
+
+// ICCCM WM_STATE value of iconified windows
+const ICONIC_STATE: c_ulong = 3;
+
+unsafe fn get_client_list(
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    atoms: &Atoms,
+) -> Vec<xlib::Window> {
+    log_trace!("Getting client list");
+    // Prefer stacking order (bottom to top), fall back to mapping order
+    get_long_property(
+        display,
+        root,
+        atoms.net_client_list_stacking,
+        xlib::XA_WINDOW,
+        MAX_LIST_ITEMS,
+    )
+    .or_else(|| {
+        get_long_property(
+            display,
+            root,
+            atoms.net_client_list,
+            xlib::XA_WINDOW,
+            MAX_LIST_ITEMS,
+        )
+    })
+    .unwrap_or_else(|| {
+        log_warn!("Failed to get client list");
+        Vec::new()
+    })
+}
+
+unsafe fn get_cardinal(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    atom: xlib::Atom,
+) -> Option<u32> {
+    get_long_property(display, window, atom, xlib::XA_CARDINAL, 1)?
+        .first()
+        .map(|&value| value as u32)
+}
+
+/// Reads a list of NUL-separated UTF-8 strings, such as `_NET_DESKTOP_NAMES`.
+unsafe fn get_utf8_list(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    atom: xlib::Atom,
+    atoms: &Atoms,
+) -> Option<Vec<String>> {
+    let mut actual_type: xlib::Atom = 0;
+    let mut actual_format: c_int = 0;
+    let mut nitems: c_ulong = 0;
+    let mut bytes_after: c_ulong = 0;
+    let mut prop: *mut c_uchar = std::ptr::null_mut();
+
+    if xlib::XGetWindowProperty(
+        display,
+        window,
+        atom,
+        0,
+        MAX_LIST_ITEMS,
+        xlib::False,
+        atoms.utf8_string,
+        &mut actual_type,
+        &mut actual_format,
+        &mut nitems,
+        &mut bytes_after,
+        &mut prop,
+    ) != 0
+        || prop.is_null()
+    {
+        return None;
+    }
+
+    let values = (actual_format == 8).then(|| {
+        let bytes = std::slice::from_raw_parts(prop, nitems as usize);
+        // The list is NUL-terminated, which leaves an empty last item
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        bytes
+            .split(|&byte| byte == 0)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    });
+    xlib::XFree(prop as *mut c_void);
+    values
+}
+
+// Upper bound on the number of items read from list properties
+const MAX_LIST_ITEMS: c_long = 4096;
+
+/// Reads a format 32 property, which Xlib returns as an array of C longs.
+unsafe fn get_long_property(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    atom: xlib::Atom,
+    req_type: xlib::Atom,
+    max_items: c_long,
+) -> Option<Vec<c_ulong>> {
+    let mut actual_type: xlib::Atom = 0;
+    let mut actual_format: c_int = 0;
+    let mut nitems: c_ulong = 0;
+    let mut bytes_after: c_ulong = 0;
+    let mut prop: *mut c_uchar = std::ptr::null_mut();
+
+    if xlib::XGetWindowProperty(
+        display,
+        window,
+        atom,
+        0,
+        max_items,
+        xlib::False,
+        req_type,
+        &mut actual_type,
+        &mut actual_format,
+        &mut nitems,
+        &mut bytes_after,
+        &mut prop,
+    ) != 0
+        || prop.is_null()
+    {
+        return None;
+    }
+
+    let values = if actual_format == 32 {
+        Some(std::slice::from_raw_parts(prop as *const c_ulong, nitems as usize).to_vec())
+    } else {
+        None
+    };
+    xlib::XFree(prop as *mut c_void);
+    values
+}
+
+/// Selects screen change notifications and reads the monitors of every
+/// screen. Returns the XRandR event base, or `None` without XRandR 1.5.
+#[cfg(feature = "xrandr")]
+unsafe fn init_xrandr(display: *mut xlib::Display, screens: &mut [Screen]) -> Option<c_int> {
+    let mut event_base = 0;
+    let mut error_base = 0;
+    if xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+        log_warn!("XRandR extension is not available, monitors are not reported");
+        return None;
+    }
+    let (mut major, mut minor) = (0, 0);
+    // XRRGetMonitors needs XRandR 1.5
+    if xrandr::XRRQueryVersion(display, &mut major, &mut minor) == 0 || (major, minor) < (1, 5) {
+        log_warn!(
+            "XRandR {}.{} lacks monitors, monitors are not reported",
+            major,
+            minor
+        );
+        return None;
+    }
+    for screen in screens {
+        xrandr::XRRSelectInput(display, screen.root, xrandr::RRScreenChangeNotifyMask);
+        screen.monitors = get_monitors(display, screen.root);
+    }
+    Some(event_base)
+}
+
+#[cfg(feature = "xrandr")]
+unsafe fn get_monitors(display: *mut xlib::Display, root: xlib::Window) -> Vec<Monitor> {
+    let mut count: c_int = 0;
+    let infos = xrandr::XRRGetMonitors(display, root, xlib::True, &mut count);
+    if infos.is_null() {
+        log_trace!("No XRandR monitors on root window {}", root);
+        return Vec::new();
+    }
+
+    let monitors: Vec<Monitor> = std::slice::from_raw_parts(infos, count.max(0) as usize)
+        .iter()
+        .map(|info| {
+            let name_ptr = xlib::XGetAtomName(display, info.name);
+            let name = if name_ptr.is_null() {
+                String::new()
+            } else {
+                let name = CStr::from_ptr(name_ptr).to_string_lossy().into_owned();
+                xlib::XFree(name_ptr as *mut c_void);
+                name
+            };
+            Monitor {
+                name,
+                primary: info.primary != 0,
+                x: info.x,
+                y: info.y,
+                width: info.width.max(0) as u32,
+                height: info.height.max(0) as u32,
+            }
+        })
+        .collect();
+    xrandr::XRRFreeMonitors(infos);
+    log_debug!("{} monitor(s) on root window {}", monitors.len(), root);
+    monitors
+}
+
+/// Finds the monitor of `screen` that shows the largest part of `window`.
+unsafe fn get_window_monitor(
+    display: *mut xlib::Display,
+    screen: &Screen,
+    window: xlib::Window,
+) -> Option<Monitor> {
+    match screen.monitors.as_slice() {
+        [] => return None,
+        // No need to ask the server where the window is
+        [monitor] => return Some(monitor.clone()),
+        _ => {}
+    }
+    let root = screen.root;
+    let mut attributes: xlib::XWindowAttributes = std::mem::zeroed();
+    if xlib::XGetWindowAttributes(display, window, &mut attributes) == 0 {
+        return None;
+    }
+    let (mut x, mut y) = (0, 0);
+    let mut child: xlib::Window = 0;
+    if xlib::XTranslateCoordinates(display, window, root, 0, 0, &mut x, &mut y, &mut child) == 0 {
+        return None;
+    }
+    log_trace!(
+        "Window {} geometry: {}x{}+{}+{}",
+        window,
+        attributes.width,
+        attributes.height,
+        x,
+        y
+    );
+
+    let overlap = |start: i32, len: i64, other_start: i32, other_len: i64| {
+        let (start, other_start) = (start as i64, other_start as i64);
+        ((start + len).min(other_start + other_len) - start.max(other_start)).max(0)
+    };
+    screen
+        .monitors
+        .iter()
+        .map(|monitor| {
+            let area = overlap(x, attributes.width as i64, monitor.x, monitor.width as i64)
+                * overlap(
+                    y,
+                    attributes.height as i64,
+                    monitor.y,
+                    monitor.height as i64,
+                );
+            (area, monitor)
+        })
+        .filter(|(area, _)| *area > 0)
+        .max_by_key(|(area, _)| *area)
+        .map(|(_, monitor)| monitor.clone())
 }
 
 unsafe extern "C" fn x_error_handler(
-    _: *mut xlib::Display,
+    display: *mut xlib::Display,
     error: *mut xlib::XErrorEvent,
 ) -> c_int {
-    // Log the error or handle it as needed
-    // For now, we'll just ignore BadWindow errors
-    if (*error).error_code == xlib::BadWindow {
+    if let Some(previous) = foreign_display_handler(display, |handlers| handlers.error) {
+        return previous.map_or(0, |previous| previous(display, error));
+    }
+    let error = &*error;
+    // Windows routinely disappear between an event and the requests about them
+    if error.error_code == xlib::BadWindow {
         log_trace!("Ignoring BadWindow error");
         return 0;
     }
-    // For other errors, print a warning
-    log_warn!("X11 error occurred: {}", (*error).error_code);
+
+    let mut buf = [0 as c_char; 256];
+    xlib::XGetErrorText(
+        display,
+        error.error_code as c_int,
+        buf.as_mut_ptr(),
+        buf.len() as c_int,
+    );
+    let error = WinshiftError::XProtocol {
+        name: CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned(),
+        error_code: error.error_code,
+        request_code: error.request_code,
+        minor_code: error.minor_code,
+        resource_id: error.resourceid,
+    };
+    log_warn!("{}", error);
+    // Reported by the session once control is back in Rust
+    X_ERRORS.with_borrow_mut(|errors| errors.push((display as usize, error)));
     0
 }
+
+unsafe extern "C" fn x_io_error_handler(display: *mut xlib::Display) -> c_int {
+    if let Some(previous) = foreign_display_handler(display, |handlers| handlers.io_error) {
+        return previous.map_or(0, |previous| previous(display));
+    }
+    log_error!("X11 connection lost");
+    0
+}
+
+/// For a display that belongs to no session, e.g. one the application opened
+/// itself, the handler that was installed before ours.
+fn foreign_display_handler<H>(
+    display: *mut xlib::Display,
+    previous: impl FnOnce(&ErrorHandlers) -> Option<H>,
+) -> Option<Option<H>> {
+    let handlers = ERROR_HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+    (!handlers.displays.contains(&(display as usize))).then(|| previous(&handlers))
+}
+
+unsafe extern "C" fn x_io_error_exit(_: *mut xlib::Display, lost: *mut c_void) {
+    // Returning instead of exiting leaves the display unusable but the process alive
+    (*(lost as *const AtomicBool)).store(true, Ordering::Release);
+}
+
+fn set_io_error_exit_handler() -> Option<SetIoErrorExitHandler> {
+    *SET_IO_ERROR_EXIT_HANDLER.get_or_init(|| unsafe {
+        let symbol = libc::dlsym(libc::RTLD_DEFAULT, c"XSetIOErrorExitHandler".as_ptr());
+        (!symbol.is_null())
+            .then(|| std::mem::transmute::<*mut c_void, SetIoErrorExitHandler>(symbol))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(policy(None)).unwrap();
+        assert_eq!(backoff.delay, Duration::from_millis(100));
+        let delays: Vec<u64> = (0..6)
+            .map(|_| {
+                assert!(backoff.failed());
+                backoff.delay.as_millis() as u64
+            })
+            .collect();
+        assert_eq!(delays, [200, 400, 800, 1000, 1000, 1000]);
+        assert_eq!(backoff.attempts, 6);
+    }
+
+    #[test]
+    fn backoff_schedules_the_next_attempt_after_the_delay() {
+        let mut backoff = Backoff::new(policy(None)).unwrap();
+        let before = Instant::now();
+        assert!(backoff.failed());
+        assert!(backoff.next_attempt >= before + Duration::from_millis(200));
+    }
+
+    #[test]
+    fn backoff_gives_up_after_max_attempts() {
+        let mut backoff = Backoff::new(policy(Some(3))).unwrap();
+        assert!(backoff.failed());
+        assert!(backoff.failed());
+        assert!(!backoff.failed());
+        assert_eq!(backoff.attempts, 3);
+    }
+
+    #[test]
+    fn backoff_without_attempts_never_starts() {
+        assert!(Backoff::new(policy(Some(0))).is_none());
+        let mut backoff = Backoff::new(policy(Some(1))).unwrap();
+        assert!(!backoff.failed());
+    }
+}
@@ -1,7 +1,6 @@
-use ctrlc;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
-use winshift::{FocusChangeHandler, WindowFocusHook};
+use winshift::{FocusChangeHandler, FocusEvent, WindowFocusHook, WinshiftError};
 use winshift::{logger, log_debug, log_error, log_info, log_warn};
 
 struct WindowChangeHandler {
@@ -10,7 +9,8 @@ struct WindowChangeHandler {
 }
 
 impl FocusChangeHandler for WindowChangeHandler {
-    fn on_focus_change(&self, window_title: String) {
+    fn on_focus_change(&self, event: &FocusEvent) {
+        let window_title = event.window.title.clone();
         let mut current = self.current_window.write().unwrap();
         let mut last_change = self.last_change.write().unwrap();
         let now = Instant::now();
@@ -20,12 +20,32 @@ impl FocusChangeHandler for WindowChangeHandler {
         if window_title.is_empty() {
             log_warn!("Received empty window title");
         } else if *current != window_title {
-            log_info!("Window changed: '{}' -> '{}'", current, window_title);
+            log_info!(
+                "Window changed: '{}' -> '{}' (class: {:?}, pid: {:?})",
+                current,
+                window_title,
+                event.window.class,
+                event.window.pid
+            );
             *current = window_title;
         } else {
             log_debug!("Window title unchanged: {}", window_title);
         }
     }
+
+    fn on_title_change(&self, event: &FocusEvent) {
+        let mut current = self.current_window.write().unwrap();
+        log_info!(
+            "Window title changed: '{}' -> '{}'",
+            current,
+            event.window.title
+        );
+        *current = event.window.title.clone();
+    }
+
+    fn on_error(&self, error: &WinshiftError) {
+        log_warn!("Window tracking degraded: {}", error);
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
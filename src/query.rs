@@ -0,0 +1,66 @@
+use crate::error::WinshiftError;
+#[cfg(feature = "xrandr")]
+use crate::event::Monitor;
+use crate::event::WindowInfo;
+use crate::log_trace;
+
+/// Returns the currently focused window, or `None` if no window has focus.
+///
+/// Opens a short-lived connection to the display, so this does not require a
+/// running [`WindowFocusHook`](crate::WindowFocusHook).
+pub fn current_focus() -> Result<Option<WindowInfo>, WinshiftError> {
+    #[cfg(target_os = "linux")]
+    {
+        log_trace!("Querying current focus on Linux platform");
+        crate::linux::current_focus()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        crate::log_error!("Unsupported platform");
+        Err(WinshiftError::PlatformError(
+            "Unsupported platform".to_string(),
+        ))
+    }
+}
+
+/// Returns every window managed by the window manager, bottom to top in stacking order.
+///
+/// Reads `_NET_CLIENT_LIST_STACKING`, falling back to `_NET_CLIENT_LIST` when
+/// the window manager does not provide a stacking order.
+pub fn list_windows() -> Result<Vec<WindowInfo>, WinshiftError> {
+    #[cfg(target_os = "linux")]
+    {
+        log_trace!("Listing windows on Linux platform");
+        crate::linux::list_windows()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        crate::log_error!("Unsupported platform");
+        Err(WinshiftError::PlatformError(
+            "Unsupported platform".to_string(),
+        ))
+    }
+}
+
+/// Returns the monitor showing most of the focused window.
+///
+/// `None` if no window has focus or the X server lacks XRandR 1.5. Needs the
+/// `xrandr` feature.
+#[cfg(feature = "xrandr")]
+pub fn current_monitor() -> Result<Option<Monitor>, WinshiftError> {
+    #[cfg(target_os = "linux")]
+    {
+        log_trace!("Querying current monitor on Linux platform");
+        crate::linux::current_monitor()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        crate::log_error!("Unsupported platform");
+        Err(WinshiftError::PlatformError(
+            "Unsupported platform".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,234 @@
+use crate::config::OverflowPolicy;
+use crate::dispatch::Dispatcher;
+use crate::event::WindowEvent;
+use crate::{log_debug, log_trace};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// A bounded queue between the event loop and the dispatcher thread.
+pub(crate) struct EventQueue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    overflow: OverflowPolicy,
+}
+
+struct QueueState {
+    events: VecDeque<WindowEvent>,
+    closed: bool,
+}
+
+impl EventQueue {
+    pub(crate) fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                // Not preallocated, the capacity may be far larger than the backlog
+                events: VecDeque::new(),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            overflow,
+        }
+    }
+
+    /// Queues an event and returns how many events were dropped to make room.
+    pub(crate) fn push(&self, event: WindowEvent) -> u64 {
+        let mut state = self.lock();
+        let mut dropped = 0;
+        if state.events.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::Block => {
+                    while state.events.len() >= self.capacity && !state.closed {
+                        state = self.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    state.events.pop_front();
+                    dropped = 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    log_trace!("Dispatch queue full, dropping newest event");
+                    return 1;
+                }
+                OverflowPolicy::CoalesceLatest => {
+                    dropped = state.events.len() as u64;
+                    state.events.clear();
+                }
+            }
+        }
+        if dropped > 0 {
+            log_trace!("Dispatch queue full, dropped {} event(s)", dropped);
+        }
+        state.events.push_back(event);
+        self.not_empty.notify_one();
+        dropped
+    }
+
+    /// Takes the next event, waiting for one. Returns `None` once the queue
+    /// is closed and drained.
+    pub(crate) fn pop(&self) -> Option<WindowEvent> {
+        let mut state = self.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                self.not_full.notify_one();
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Lets the dispatcher thread exit once it has handled the queued events.
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Starts a thread that dispatches queued events until the queue is closed.
+///
+/// `on_failure` is called when a handler asked to stop the hook, so the event
+/// loop can wake up and collect the error.
+pub(crate) fn spawn_dispatcher(
+    queue: Arc<EventQueue>,
+    dispatcher: Arc<Dispatcher>,
+    on_failure: impl Fn() + Send + 'static,
+) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("winshift-dispatch".to_string())
+        .spawn(move || {
+            log_debug!("Dispatcher thread started");
+            while let Some(event) = queue.pop() {
+                dispatcher.dispatch(event);
+                if dispatcher.has_failure() {
+                    on_failure();
+                }
+            }
+            log_debug!("Dispatcher thread stopped");
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PanicPolicy;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn event(n: u32) -> WindowEvent {
+        WindowEvent::Disconnected(Arc::from(format!(":{}", n)))
+    }
+
+    fn display(event: WindowEvent) -> String {
+        match event {
+            WindowEvent::Disconnected(display) => display.to_string(),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    /// Closes the queue and returns what was left in it.
+    fn drain(queue: &EventQueue) -> Vec<String> {
+        queue.close();
+        std::iter::from_fn(|| queue.pop()).map(display).collect()
+    }
+
+    #[test]
+    fn huge_capacity_does_not_allocate_up_front() {
+        let queue = EventQueue::new(usize::MAX, OverflowPolicy::Block);
+        assert_eq!(queue.push(event(0)), 0);
+        assert_eq!(drain(&queue), [":0"]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let queue = Arc::new(EventQueue::new(1, OverflowPolicy::Block));
+        assert_eq!(queue.push(event(0)), 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let pusher = {
+            let queue = queue.clone();
+            thread::spawn(move || sender.send(queue.push(event(1))).unwrap())
+        };
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+
+        assert_eq!(queue.pop().map(display).as_deref(), Some(":0"));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(0));
+        pusher.join().unwrap();
+        assert_eq!(drain(&queue), [":1"]);
+    }
+
+    #[test]
+    fn close_releases_a_blocked_push() {
+        let queue = Arc::new(EventQueue::new(1, OverflowPolicy::Block));
+        queue.push(event(0));
+        let pusher = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(event(1)))
+        };
+        thread::sleep(Duration::from_millis(20));
+        queue.close();
+        assert_eq!(pusher.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_events() {
+        let queue = EventQueue::new(2, OverflowPolicy::DropOldest);
+        let dropped: u64 = (0..4).map(|n| queue.push(event(n))).sum();
+        assert_eq!(dropped, 2);
+        assert_eq!(drain(&queue), [":2", ":3"]);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_first_events() {
+        let queue = EventQueue::new(2, OverflowPolicy::DropNewest);
+        let dropped: u64 = (0..4).map(|n| queue.push(event(n))).sum();
+        assert_eq!(dropped, 2);
+        assert_eq!(drain(&queue), [":0", ":1"]);
+    }
+
+    #[test]
+    fn coalesce_latest_replaces_a_full_queue() {
+        let queue = EventQueue::new(2, OverflowPolicy::CoalesceLatest);
+        assert_eq!(queue.push(event(0)), 0);
+        assert_eq!(queue.push(event(1)), 0);
+        assert_eq!(queue.push(event(2)), 2);
+        assert_eq!(queue.push(event(3)), 0);
+        assert_eq!(drain(&queue), [":2", ":3"]);
+    }
+
+    #[test]
+    fn dispatcher_thread_delivers_in_order_and_counts_drops() {
+        let queue = Arc::new(EventQueue::new(8, OverflowPolicy::DropNewest));
+        let dispatcher = Arc::new(Dispatcher::new(PanicPolicy::default()));
+        let (sender, receiver) = mpsc::channel();
+        dispatcher.subscribe(sender);
+
+        // Fill the queue before the thread starts so the overflow is deterministic
+        for n in 0..10 {
+            dispatcher.record_dropped(queue.push(event(n)));
+        }
+        assert_eq!(dispatcher.dropped_events(), 2);
+
+        let thread = spawn_dispatcher(queue.clone(), dispatcher, || {}).unwrap();
+        queue.close();
+        thread.join().unwrap();
+        let delivered: Vec<String> = receiver.try_iter().map(display).collect();
+        let expected: Vec<String> = (0..8).map(|n| format!(":{}", n)).collect();
+        assert_eq!(delivered, expected);
+    }
+}
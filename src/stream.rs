@@ -0,0 +1,138 @@
+use crate::config::{EventKinds, HookConfig};
+use crate::error::WinshiftError;
+use crate::event::WindowEvent;
+use crate::linux::Session;
+use crate::{log_debug, log_error, log_trace, log_warn};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::unix::AsyncFd;
+use tokio::time::Sleep;
+
+/// An asynchronous stream of window events.
+///
+/// The X11 connections are registered with the tokio reactor, so no thread is
+/// blocked while waiting for events. The connections are closed when the
+/// stream is dropped. A lost connection is reported as
+/// [`WindowEvent::Disconnected`] and not reopened; the stream ends once every
+/// display is gone. Must be created from within a tokio runtime.
+pub struct FocusStream {
+    sessions: Vec<AsyncFd<Session>>,
+    events: EventKinds,
+    pending: VecDeque<WindowEvent>,
+    // Fires when a focus change waiting to settle is due
+    settle_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl FocusStream {
+    pub fn new() -> Result<Self, WinshiftError> {
+        Self::with_config(HookConfig::default())
+    }
+
+    pub(crate) fn with_config(config: HookConfig) -> Result<Self, WinshiftError> {
+        log_debug!("Creating new FocusStream");
+        let displays: Vec<Option<&str>> = if config.displays.is_empty() {
+            vec![None]
+        } else {
+            config
+                .displays
+                .iter()
+                .map(|name| Some(name.as_str()))
+                .collect()
+        };
+
+        let mut pending = VecDeque::new();
+        let mut sessions = Vec::with_capacity(displays.len());
+        for display in displays {
+            let mut session = Session::open(display, config.clone())?;
+            session.start(&mut |event| pending.push_back(event));
+            sessions.push(AsyncFd::new(session).map_err(|e| {
+                log_error!("Failed to register X11 connection with the reactor: {}", e);
+                WinshiftError::Io(e)
+            })?);
+        }
+        Ok(Self {
+            sessions,
+            events: config.events,
+            pending,
+            settle_timer: None,
+        })
+    }
+}
+
+impl Stream for FocusStream {
+    type Item = WindowEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            if this.sessions.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            // Xlib may already have buffered events while reading replies, so
+            // always drain its queue before waiting on the sockets
+            let now = Instant::now();
+            let pending = &mut this.pending;
+            let events = this.events;
+            this.sessions.retain_mut(|session| {
+                let session = session.get_mut();
+                session.process_pending(&mut |event| pending.push_back(event));
+                session.flush_due(now, &mut |event| pending.push_back(event));
+                if !session.is_lost() {
+                    return true;
+                }
+                log_warn!("X11 connection to {} lost", session.name());
+                let event = WindowEvent::Disconnected(session.name());
+                if events.matches(&event) {
+                    pending.push_back(event);
+                }
+                false
+            });
+            if !this.pending.is_empty() || this.sessions.is_empty() {
+                continue;
+            }
+
+            let deadline = this
+                .sessions
+                .iter()
+                .filter_map(|session| session.get_ref().next_deadline())
+                .min();
+            if let Some(deadline) = deadline {
+                let deadline = tokio::time::Instant::from_std(deadline);
+                let timer = this
+                    .settle_timer
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+                timer.as_mut().reset(deadline);
+                if timer.as_mut().poll(cx).is_ready() {
+                    continue;
+                }
+            }
+
+            log_trace!("Waiting for X11 connections to become readable");
+            let mut readable = false;
+            for session in &mut this.sessions {
+                match session.poll_read_ready_mut(cx) {
+                    Poll::Ready(Ok(mut guard)) => {
+                        guard.clear_ready();
+                        readable = true;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        log_error!("Failed to poll X11 connection: {}", e);
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => {}
+                }
+            }
+            if !readable {
+                return Poll::Pending;
+            }
+        }
+    }
+}